@@ -0,0 +1,315 @@
+// ══════════════════════════════════════════════════════════════════
+// Completion — Tab in the line editor and `nes --completions <shell>`
+// ══════════════════════════════════════════════════════════════════
+//
+// Both are driven by the same tables: `BUILTINS` for command names and
+// `ARGS` for what the arguments of each built-in are.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+
+use crate::{expand, platform, Shell, BUILTINS};
+
+/// What the arguments of a command complete to.
+#[derive(Clone, Copy, PartialEq)]
+enum Arg {
+    Files,
+    Dirs,
+    /// `.nes` / `.nest` files and directories.
+    Scripts,
+    Commands,
+    Vars,
+    Aliases,
+    /// `%1`, `%2`… for the session's background jobs.
+    Jobs,
+    Words(&'static [&'static str]),
+}
+
+/// Built-ins whose arguments are not just paths.
+const ARGS: &[(&str, Arg)] = &[
+    ("cd", Arg::Dirs), ("pushd", Arg::Dirs), ("mkdir", Arg::Dirs), ("tree", Arg::Dirs), ("find", Arg::Dirs), ("count", Arg::Dirs),
+    ("run", Arg::Scripts),
+    ("help", Arg::Commands), ("which", Arg::Commands),
+    ("let", Arg::Vars), ("local", Arg::Vars), ("unset", Arg::Vars), ("export", Arg::Vars), ("read", Arg::Vars),
+    ("alias", Arg::Aliases),
+    ("fg", Arg::Jobs), ("bg", Arg::Jobs), ("wait", Arg::Jobs), ("kill", Arg::Jobs),
+    ("set", Arg::Words(&["-e", "+e"])),
+    ("history", Arg::Words(&["-c", "search"])),
+    ("trap", Arg::Words(&["EXIT", "INT", "TERM"])),
+];
+
+/// Block keywords, completed where a command may start.
+const KEYWORDS: &[&str] = &[
+    "if", "elif", "else", "end", "for", "while", "until", "fn", "def", "match", "case", "default",
+];
+
+/// Words after which a command starts: `if grep -q …`, `while not exists …`.
+const CONDITION_WORDS: &[&str] = &["if", "elif", "while", "until", "and", "or", "not"];
+
+/// Where the word that ends `line` starts (a byte offset), and its text
+/// with the quotes removed. Separators inside quotes do not end it.
+fn last_word(line: &str) -> (usize, String) {
+    let (mut start, mut word, mut quote) = (0, String::new(), None);
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.push(c),
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c.is_whitespace() || "|;&<>(".contains(c) => { start = i + c.len_utf8(); word.clear(); }
+            None => word.push(c),
+        }
+    }
+    (start, word)
+}
+
+fn arg_kind(command: &str) -> Arg {
+    ARGS.iter().find(|(c, _)| *c == command).map_or(Arg::Files, |&(_, a)| a)
+}
+
+/// Whether `name` extends the typed `prefix` (ignoring case on Windows).
+fn extends(name: &str, prefix: &str) -> bool {
+    if cfg!(windows) { name.to_lowercase().starts_with(&prefix.to_lowercase()) } else { name.starts_with(prefix) }
+}
+
+/// Entries of the directory in `word` that extend its last component,
+/// with `/` after directories. Hidden files only match a typed `.`.
+fn paths(word: &str, keep: impl Fn(&str, bool) -> bool) -> Vec<String> {
+    let split = word.rfind(['/', std::path::MAIN_SEPARATOR]).map_or(0, |i| i + 1);
+    let (dir, prefix) = word.split_at(split);
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { ".".into() } else { expand::expand_tilde(dir) }) else { return Vec::new() };
+    let mut found: Vec<String> = entries.flatten().filter_map(|e| {
+        let name = e.file_name().to_string_lossy().into_owned();
+        if !extends(&name, prefix) || name.starts_with('.') && !prefix.starts_with('.') { return None; }
+        let is_dir = e.path().is_dir();
+        keep(&name, is_dir).then(|| format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }))
+    }).collect();
+    found.sort();
+    found
+}
+
+/// Executables in the `PATH` directories.
+fn path_commands() -> Vec<String> {
+    let Some(path) = env::var_os("PATH") else { return Vec::new() };
+    env::split_paths(&path).filter_map(|d| fs::read_dir(d).ok())
+        .flat_map(|entries| entries.flatten().filter_map(|e| platform::command_name(&e.path())))
+        .collect()
+}
+
+impl Shell {
+    /// Completions for the word that ends `line` (the input up to the
+    /// cursor), and the char offset where that word starts.
+    pub fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        let (start, word) = last_word(line);
+        let word = word.as_str();
+        let offset = line[..start].chars().count();
+        let segment = line[..start].rsplit(['|', ';', '&', '(']).next().unwrap_or("");
+        let words: Vec<&str> = segment.split_whitespace().collect();
+        let command_pos = words.last().is_none_or(|w| CONDITION_WORDS.contains(w) && CONDITION_WORDS.contains(&words[0]));
+
+        let found: BTreeSet<String> = if let Some(name) = word.strip_prefix("${").or_else(|| word.strip_prefix('$')) {
+            let braced = word.starts_with("${");
+            self.var_names().into_iter().filter(|v| v.starts_with(name))
+                .map(|v| if braced { format!("${{{}}}", v) } else { format!("${}", v) }).collect()
+        } else if command_pos && !word.contains(['/', '\\']) {
+            self.command_names(true).into_iter().chain(KEYWORDS.iter().map(|k| k.to_string()))
+                .filter(|c| extends(c, word)).collect()
+        } else {
+            let kind = if command_pos { Arg::Files } else { arg_kind(words[0]) };
+            let names: Vec<String> = match kind {
+                Arg::Files => return (offset, paths(word, |_, _| true)),
+                Arg::Dirs => return (offset, paths(word, |_, dir| dir)),
+                Arg::Scripts => return (offset, paths(word, |n, dir| dir || n.ends_with(".nes") || n.ends_with(".nest"))),
+                Arg::Commands => self.command_names(false),
+                Arg::Vars => self.var_names(),
+                Arg::Aliases => self.vars.keys().filter_map(|k| k.strip_prefix("_alias_")).map(String::from).collect(),
+                Arg::Jobs => self.jobs.iter().map(|j| format!("%{}", j.id)).collect(),
+                Arg::Words(list) => list.iter().map(|w| w.to_string()).collect(),
+            };
+            names.into_iter().filter(|n| extends(n, word)).collect()
+        };
+        (offset, found.into_iter().collect())
+    }
+
+    /// Built-ins, functions and aliases, and with `path` the programs on `PATH`.
+    fn command_names(&self, path: bool) -> Vec<String> {
+        let mut names: Vec<String> = BUILTINS.iter().map(|b| b.to_string()).collect();
+        names.extend(self.functions.keys().cloned());
+        names.extend(self.vars.keys().filter_map(|k| k.strip_prefix("_alias_")).map(String::from));
+        if path { names.extend(path_commands()); }
+        names
+    }
+
+    /// Shell and environment variable names.
+    fn var_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.vars.keys().filter(|k| !k.starts_with("_alias_")).cloned().collect();
+        names.extend(env::vars_os().map(|(k, _)| k.to_string_lossy().into_owned()));
+        names
+    }
+}
+
+// ── Completion scripts for other shells ───────────────────────
+
+/// Commands `nes` accepts as its first argument.
+fn subcommands() -> String {
+    let mut names: Vec<&str> = BUILTINS.to_vec();
+    names.push("enter-full");
+    names.join(" ")
+}
+
+/// The `ARGS` entries grouped by kind, in table order. Aliases and jobs
+/// only exist inside a session, so they are left out.
+fn arg_groups() -> Vec<(Arg, Vec<&'static str>)> {
+    let mut groups: Vec<(Arg, Vec<&str>)> = Vec::new();
+    for &(cmd, kind) in ARGS {
+        if matches!(kind, Arg::Aliases | Arg::Jobs) { continue; }
+        match groups.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, cmds)) => cmds.push(cmd),
+            None => groups.push((kind, vec![cmd])),
+        }
+    }
+    groups
+}
+
+fn words_of(kind: Arg) -> String {
+    match kind {
+        Arg::Commands => BUILTINS.join(" "),
+        Arg::Words(list) => list.join(" "),
+        _ => String::new(),
+    }
+}
+
+/// Completion script for `nes` in `shell`: `bash`, `zsh`, `fish` or
+/// `powershell`.
+pub fn script(shell: &str) -> Option<String> {
+    let subs = subcommands();
+    let mut s = String::new();
+    match shell {
+        "bash" => {
+            s.push_str("# nes completion for bash: source this file, e.g. from ~/.bashrc\n_nes() {\n");
+            s.push_str("    local cur=${COMP_WORDS[COMP_CWORD]}\n");
+            s.push_str(&format!("    if [ \"$COMP_CWORD\" -eq 1 ]; then\n        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n        return\n    fi\n", subs));
+            s.push_str("    case ${COMP_WORDS[1]} in\n");
+            for (kind, cmds) in arg_groups() {
+                let reply = match kind {
+                    Arg::Dirs => "compgen -d -- \"$cur\"".to_string(),
+                    Arg::Scripts => "compgen -d -- \"$cur\"; compgen -f -X '!*.nes' -- \"$cur\"; compgen -f -X '!*.nest' -- \"$cur\"".to_string(),
+                    Arg::Vars => "compgen -v -- \"$cur\"".to_string(),
+                    _ => format!("compgen -W \"{}\" -- \"$cur\"", words_of(kind)),
+                };
+                s.push_str(&format!("        {}) COMPREPLY=($({})) ;;\n", cmds.join("|"), reply));
+            }
+            s.push_str("        *) COMPREPLY=($(compgen -f -- \"$cur\")) ;;\n    esac\n}\n");
+            s.push_str("complete -o filenames -F _nes nes\n");
+        }
+        "zsh" => {
+            s.push_str("#compdef nes\n# nes completion for zsh: source this file after compinit\n_nes() {\n");
+            s.push_str(&format!("    if (( CURRENT == 2 )); then\n        compadd -- {} && return\n    fi\n", subs));
+            s.push_str("    case $words[2] in\n");
+            for (kind, cmds) in arg_groups() {
+                let action = match kind {
+                    Arg::Dirs => "_files -/".to_string(),
+                    Arg::Scripts => "_files -g '*.(nes|nest)'".to_string(),
+                    Arg::Vars => "_parameters".to_string(),
+                    _ => format!("compadd -- {}", words_of(kind)),
+                };
+                s.push_str(&format!("        {}) {} ;;\n", cmds.join("|"), action));
+            }
+            s.push_str("        *) _files ;;\n    esac\n}\ncompdef _nes nes\n");
+        }
+        "fish" => {
+            s.push_str("# nes completion for fish: save as ~/.config/fish/completions/nes.fish\n");
+            s.push_str(&format!("complete -c nes -f -n __fish_use_subcommand -a '{}'\n", subs));
+            let mut special = Vec::new();
+            for (kind, cmds) in arg_groups() {
+                let args = match kind {
+                    Arg::Dirs => "-a '(__fish_complete_directories)'".to_string(),
+                    Arg::Scripts => "-a '(__fish_complete_suffix .nes; __fish_complete_suffix .nest)'".to_string(),
+                    Arg::Vars => "-a '(set -n)'".to_string(),
+                    _ => format!("-a '{}'", words_of(kind)),
+                };
+                s.push_str(&format!("complete -c nes -f -n '__fish_seen_subcommand_from {}' {}\n", cmds.join(" "), args));
+                special.extend(cmds);
+            }
+            s.push_str(&format!("complete -c nes -F -n 'not __fish_use_subcommand; and not __fish_seen_subcommand_from {}'\n", special.join(" ")));
+        }
+        "powershell" | "pwsh" => {
+            s.push_str("# nes completion for PowerShell: dot-source this file from $PROFILE\n");
+            s.push_str("Register-ArgumentCompleter -Native -CommandName nes -ScriptBlock {\n");
+            s.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n");
+            s.push_str("    $words = @($commandAst.CommandElements | ForEach-Object { $_.ToString() })\n");
+            s.push_str("    $result = { param($names) $names | Where-Object { $_ -like \"$wordToComplete*\" } |\n");
+            s.push_str("        ForEach-Object { [System.Management.Automation.CompletionResult]::new($_) } }\n");
+            s.push_str("    if ($words.Count -lt 2 -or ($words.Count -eq 2 -and $wordToComplete)) {\n");
+            s.push_str(&format!("        return & $result ('{}' -split ' ')\n    }}\n", subs));
+            s.push_str("    switch ($words[1]) {\n");
+            for (kind, cmds) in arg_groups() {
+                let names: Vec<String> = cmds.iter().map(|c| format!("'{}'", c)).collect();
+                let action = match kind {
+                    Arg::Dirs => "& $result (Get-ChildItem -Directory -Name)".to_string(),
+                    Arg::Scripts => "& $result (Get-ChildItem -Name | Where-Object { (Test-Path $_ -PathType Container) -or $_ -match '\\.nest?$' })".to_string(),
+                    Arg::Vars => "& $result (Get-ChildItem Env: | ForEach-Object Name)".to_string(),
+                    _ => format!("& $result ('{}' -split ' ')", words_of(kind)),
+                };
+                s.push_str(&format!("        {{ $_ -in {} }} {{ {} }}\n", names.join(", "), action));
+            }
+            s.push_str("    }\n}\n");
+        }
+        _ => return None,
+    }
+    Some(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_word_skips_quotes() {
+        assert_eq!(last_word("cat src/ma"), (4, "src/ma".to_string()));
+        assert_eq!(last_word("cat 'a&b"), (4, "a&b".to_string()));
+        assert_eq!(last_word("ls|grep \"x y\"z"), (8, "x yz".to_string()));
+        assert_eq!(last_word("echo ok; "), (9, String::new()));
+    }
+
+    #[test]
+    fn scripts_leave_shell_options_alone() {
+        let bash = script("bash").unwrap();
+        assert!(!bash.contains("shopt"));
+        assert!(bash.contains("compgen -f -X '!*.nes'"));
+        for shell in ["zsh", "fish", "powershell"] { assert!(script(shell).is_some(), "{}", shell); }
+        assert!(script("tcsh").is_none());
+    }
+
+    #[test]
+    fn candidates() {
+        let mut sh = Shell::new();
+        sh.vars.insert("nes_test_var".into(), "1".into());
+        sh.vars.insert("_alias_nes_ll".into(), "ls -l".into());
+        let found = |sh: &Shell, line: &str| sh.complete(line).1;
+        assert_eq!(sh.complete("echo $nes_te"), (5, vec!["$nes_test_var".to_string()]));
+        assert_eq!(found(&sh, "echo ${nes_te"), ["${nes_test_var}"]);
+        assert_eq!(found(&sh, "unset nes_te"), ["nes_test_var"]);
+        assert_eq!(found(&sh, "alias nes_"), ["nes_ll"]);
+        assert_eq!(found(&sh, "nes_l"), ["nes_ll"]);
+        assert_eq!(found(&sh, "trap E"), ["EXIT"]);
+        assert!(found(&sh, "whi").contains(&"while".to_string()));
+        assert!(found(&sh, "if exis").contains(&"exists".to_string()));
+        assert!(found(&sh, "echo x | gre").contains(&"grep".to_string()));
+        assert!(found(&sh, "help whil").is_empty());
+
+        let root = env::temp_dir().join(format!("nes-complete-{}", std::process::id()));
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        for f in ["a.nes", "b.txt", ".hidden"] { fs::write(root.join(f), "").unwrap(); }
+        let base = format!("{}/", root.to_string_lossy().replace('\\', "/"));
+        let rel = |line: &str| -> Vec<String> {
+            found(&sh, &format!("{}{}", line, base)).iter().map(|f| f[base.len()..].to_string()).collect()
+        };
+        assert_eq!(rel("cat "), ["a.nes", "b.txt", "sub dir/"]);
+        assert_eq!(rel("cd "), ["sub dir/"]);
+        assert_eq!(rel("run "), ["a.nes", "sub dir/"]);
+        assert_eq!(found(&sh, &format!("cat '{}sub", base)), [format!("{}sub dir/", base)]);
+        assert_eq!(found(&sh, &format!("cat {}.h", base)), [format!("{}.hidden", base)]);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,295 @@
+// ══════════════════════════════════════════════════════════════════
+// Conditions — the test language of `if`, `elif`, `while` and `until`
+// ══════════════════════════════════════════════════════════════════
+//
+//   cond    := and ("or" and)*
+//   and     := not ("and" not)*
+//   not     := ("not" | "!") not | primary
+//   primary := "(" cond ")" | UNARY word | word BINARY word | words...
+//
+// A lone word is true unless it is empty, `false` or `0` — or, if it
+// names a command, when that command succeeds. Several words are run as
+// a command line.
+
+use std::fs;
+use std::path::Path;
+
+use crate::pipeline::Sink;
+use crate::regex::Regex;
+use crate::{platform, syntax, Shell};
+
+const UNARY: &[&str] = &["exists", "is-file", "is-dir", "empty"];
+const BINARY: &[&str] = &[
+    "==", "!=", "=~", "<", ">", "<=", ">=", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "newer-than",
+];
+
+enum Cond {
+    Or(Box<Cond>, Box<Cond>),
+    And(Box<Cond>, Box<Cond>),
+    Not(Box<Cond>),
+    Unary(String, String),
+    Binary(String, String, String),
+    Value(String),
+    Command(String),
+}
+
+/// Split a condition into words, keeping quotes, `$(...)` and `${...}`
+/// intact and making `(` and `)` separate tokens.
+fn tokenize(src: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut toks = Vec::new();
+    let mut cur = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let end = match c {
+            c if c.is_whitespace() => {
+                if !cur.is_empty() { toks.push(std::mem::take(&mut cur)); }
+                i += 1;
+                continue;
+            }
+            '(' if cur.is_empty() => { toks.push("(".into()); i += 1; continue; }
+            ')' => {
+                if !cur.is_empty() { toks.push(std::mem::take(&mut cur)); }
+                toks.push(")".into());
+                i += 1;
+                continue;
+            }
+            '\'' => (i + 1..chars.len()).find(|&j| chars[j] == '\'').ok_or("unterminated single quote")?,
+            '"' => {
+                let mut j = i + 1;
+                while j < chars.len() && chars[j] != '"' {
+                    if chars[j] == '\\' { j += 1; }
+                    j += 1;
+                }
+                if j >= chars.len() { return Err("unterminated double quote".into()); }
+                j
+            }
+            '$' if chars.get(i + 1) == Some(&'(') => {
+                syntax::subst_end(&chars, i + 2).ok_or("unterminated command substitution")?
+            }
+            '$' if chars.get(i + 1) == Some(&'{') => {
+                syntax::brace_end(&chars, i + 2).ok_or("unterminated '${'")?
+            }
+            _ => i,
+        };
+        cur.extend(&chars[i..=end]);
+        i = end + 1;
+    }
+    if !cur.is_empty() { toks.push(cur); }
+    Ok(toks)
+}
+
+struct Parser<'a> {
+    toks: &'a [String],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&str> { self.toks.get(self.pos).map(String::as_str) }
+
+    fn eat(&mut self, word: &str) -> bool {
+        let hit = self.peek() == Some(word);
+        if hit { self.pos += 1; }
+        hit
+    }
+
+    fn or(&mut self) -> Result<Cond, String> {
+        let mut cond = self.and()?;
+        while self.eat("or") { cond = Cond::Or(Box::new(cond), Box::new(self.and()?)); }
+        Ok(cond)
+    }
+
+    fn and(&mut self) -> Result<Cond, String> {
+        let mut cond = self.not()?;
+        while self.eat("and") { cond = Cond::And(Box::new(cond), Box::new(self.not()?)); }
+        Ok(cond)
+    }
+
+    fn not(&mut self) -> Result<Cond, String> {
+        if self.eat("not") || self.eat("!") { return Ok(Cond::Not(Box::new(self.not()?))); }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Cond, String> {
+        if self.eat("(") {
+            let cond = self.or()?;
+            if !self.eat(")") { return Err("expected ')' in condition".into()); }
+            return Ok(cond);
+        }
+        let start = self.pos;
+        while self.peek().is_some_and(|t| !matches!(t, "and" | "or" | ")")) { self.pos += 1; }
+        Ok(match &self.toks[start..self.pos] {
+            [] => return Err(match self.peek() {
+                Some(t) => format!("expected a condition before '{}'", t),
+                None => "expected a condition".into(),
+            }),
+            [op, arg] if UNARY.contains(&op.as_str()) => Cond::Unary(op.clone(), arg.clone()),
+            [l, op, r] if BINARY.contains(&op.as_str()) => Cond::Binary(l.clone(), op.clone(), r.clone()),
+            [word] => Cond::Value(word.clone()),
+            words => Cond::Command(words.join(" ")),
+        })
+    }
+}
+
+impl Shell {
+    /// Evaluate the condition of an `if`, `elif`, `while` or `until` line.
+    pub fn eval_condition(&mut self, src: &str, out: &mut dyn Sink) -> Result<bool, String> {
+        let toks = tokenize(src)?;
+        let mut parser = Parser { toks: &toks, pos: 0 };
+        let cond = parser.or()?;
+        if let Some(t) = parser.peek() { return Err(format!("unexpected '{}' in condition", t)); }
+        self.eval(&cond, out)
+    }
+
+    fn eval(&mut self, cond: &Cond, out: &mut dyn Sink) -> Result<bool, String> {
+        Ok(match cond {
+            Cond::Or(l, r) => self.eval(l, out)? || self.eval(r, out)?,
+            Cond::And(l, r) => self.eval(l, out)? && self.eval(r, out)?,
+            Cond::Not(c) => !self.eval(c, out)?,
+            Cond::Unary(op, arg) => {
+                let arg = self.operand(arg)?;
+                let path = Path::new(&arg);
+                match op.as_str() {
+                    "exists" => path.exists(),
+                    "is-file" => path.is_file(),
+                    "is-dir" => path.is_dir(),
+                    _ => match fs::metadata(path) {
+                        Ok(m) if m.is_dir() => fs::read_dir(path).is_ok_and(|mut d| d.next().is_none()),
+                        Ok(m) => m.len() == 0,
+                        Err(_) => false,
+                    },
+                }
+            }
+            Cond::Binary(l, op, r) => {
+                let (l, r) = (self.operand(l)?, self.operand(r)?);
+                compare(&l, op, &r)?
+            }
+            Cond::Value(word) => {
+                let plain = !word.contains(['$', '\'', '"']);
+                if plain && word != "true" && word != "false"
+                    && (self.is_builtin(word) || platform::find_executable(word).is_some())
+                {
+                    self.run_condition(word, out)
+                } else {
+                    let v = self.operand(word)?;
+                    !v.is_empty() && v != "false" && v != "0"
+                }
+            }
+            Cond::Command(line) => self.run_condition(line, out),
+        })
+    }
+
+    /// Expand one condition word to a single string (no splitting or
+    /// wildcards, so `*.rs` stays a pattern).
+    fn operand(&mut self, word: &str) -> Result<String, String> {
+        let words = syntax::parse_words(word, self.lineno).map_err(|e| e.to_string())?;
+        let mut vals = Vec::with_capacity(words.len());
+        for w in &words { vals.push(self.expand_text(w)?); }
+        Ok(vals.join(" "))
+    }
+
+    /// Run a command as a condition: true when it exits with status 0.
+    /// `set -e` does not apply to it.
+    fn run_condition(&mut self, line: &str, out: &mut dyn Sink) -> bool {
+        let errexit = std::mem::replace(&mut self.errexit, false);
+        self.exec(line, out);
+        self.errexit = errexit;
+        self.status == 0
+    }
+}
+
+fn compare(l: &str, op: &str, r: &str) -> Result<bool, String> {
+    let num = |s: &str| s.trim().parse::<f64>().map_err(|_| format!("{}: '{}' is not a number", op, s));
+    let mtime = |p: &str| fs::metadata(p).and_then(|m| m.modified()).ok();
+    Ok(match op {
+        "==" => l == r,
+        "!=" => l != r,
+        "=~" => Regex::new(r)?.is_match(l),
+        "newer-than" => match (mtime(l), mtime(r)) {
+            (Some(a), Some(b)) => a > b,
+            (Some(_), None) => true,
+            _ => false,
+        },
+        _ => {
+            let (a, b) = (num(l)?, num(r)?);
+            match op {
+                "<" | "-lt" => a < b,
+                ">" | "-gt" => a > b,
+                "<=" | "-le" => a <= b,
+                ">=" | "-ge" => a >= b,
+                "-eq" => a == b,
+                _ => a != b,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn check(sh: &mut Shell, src: &str) -> Result<bool, String> {
+        sh.eval_condition(src, &mut Vec::new())
+    }
+
+    fn holds(src: &str) -> bool {
+        check(&mut Shell::new(), src).unwrap_or_else(|e| panic!("{}: {}", src, e))
+    }
+
+    #[test]
+    fn precedence() {
+        assert!(!holds("false or true and false"));
+        assert!(holds("true or true and false"));
+        assert!(!holds("not false and false"));
+        assert!(holds("not (false and false)"));
+        assert!(holds("(false or true) and true"));
+        assert!(holds("! ! true"));
+    }
+
+    #[test]
+    fn comparisons() {
+        let mut sh = Shell::new();
+        sh.vars.insert("v".into(), "10".into());
+        for src in ["$v > 9", "$v -eq 10.0", "$v <= 10 and 2 < $v", "abc == abc", "\"a b\" != a", "v1.2 =~ ^v\\d+\\.\\d"] {
+            assert!(check(&mut sh, src).unwrap(), "{}", src);
+        }
+        assert!(!check(&mut sh, "$v < 9").unwrap());
+        assert_eq!(check(&mut sh, "abc < 3"), Err("<: 'abc' is not a number".to_string()));
+        assert_eq!(check(&mut sh, "1 -gt $unset_zz"), Err("-gt: '' is not a number".to_string()));
+        assert!(check(&mut sh, "a =~ (").is_err());
+    }
+
+    #[test]
+    fn file_tests_and_commands() {
+        let dir = env::temp_dir().join(format!("nes-cond-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("notes.txt"), "needle\n").unwrap();
+        fs::write(dir.join("blank.txt"), "").unwrap();
+        let d = dir.to_string_lossy().replace('\\', "/");
+        assert!(holds(&format!("exists {0}/notes.txt and is-file {0}/notes.txt and is-dir {0}/sub", d)));
+        assert!(holds(&format!("not exists {0}/missing and not is-dir {0}/notes.txt", d)));
+        assert!(holds(&format!("empty {0}/blank.txt and empty {0}/sub and not empty {0}", d)));
+        assert!(holds(&format!("grep -q needle {}/notes.txt", d)));
+        assert!(!holds(&format!("grep -q thread {}/notes.txt", d)));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn values() {
+        assert!(holds("some-word") && holds("1") && holds("'false '"));
+        assert!(!holds("0") && !holds("false") && !holds("''") && !holds("$unset_zz"));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let mut sh = Shell::new();
+        assert_eq!(check(&mut sh, "(true"), Err("expected ')' in condition".to_string()));
+        assert_eq!(check(&mut sh, "true )"), Err("unexpected ')' in condition".to_string()));
+        assert_eq!(check(&mut sh, "true and"), Err("expected a condition".to_string()));
+        assert_eq!(check(&mut sh, "or true"), Err("expected a condition before 'or'".to_string()));
+        assert!(check(&mut sh, "'open").is_err());
+    }
+}
@@ -0,0 +1,300 @@
+// ══════════════════════════════════════════════════════════════════
+// Directories — cd, CDPATH, the directory stack and `z` jumps
+// ══════════════════════════════════════════════════════════════════
+//
+// Interactive sessions record every directory they change to in a
+// small database; `z` ranks its entries by frecency — how often and
+// how recently each was visited — the way the `z` script does.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::pipeline::Sink;
+use crate::{os_reason, platform, unix_secs, Shell};
+
+/// Total rank after which the entries of the database are aged, so
+/// directories no longer used drop out.
+const MAX_RANK: f64 = 9000.0;
+
+/// A directory in the `z` database.
+struct Visit {
+    path: String,
+    rank: f64,
+    /// Unix time of the last visit.
+    time: u64,
+}
+
+/// `NES_ZFILE`, or `dirs` in the config directory.
+fn z_file() -> Option<PathBuf> {
+    env::var_os("NES_ZFILE").filter(|f| !f.is_empty()).map(PathBuf::from)
+        .or_else(|| platform::config_dir().map(|d| d.join("dirs")))
+}
+
+/// Entries of the database, one `path|rank|time` line each.
+fn load_visits() -> Vec<Visit> {
+    let Some(text) = z_file().and_then(|f| fs::read_to_string(f).ok()) else { return Vec::new() };
+    text.lines().filter_map(|line| {
+        let mut fields = line.rsplitn(3, '|');
+        let time = fields.next()?.parse().ok()?;
+        let rank = fields.next()?.parse().ok()?;
+        Some(Visit { path: fields.next()?.to_string(), rank, time })
+    }).collect()
+}
+
+fn save_visits(visits: &[Visit]) {
+    let Some(path) = z_file() else { return };
+    if let Some(dir) = path.parent() { let _ = fs::create_dir_all(dir); }
+    let text: String = visits.iter().map(|v| format!("{}|{}|{}\n", v.path, v.rank, v.time)).collect();
+    let _ = fs::write(path, text);
+}
+
+/// Visit count weighted by how long ago the last visit was.
+fn frecency(v: &Visit, now: u64) -> f64 {
+    let weight = match now.saturating_sub(v.time) {
+        0..3600 => 4.0,
+        3600..86_400 => 2.0,
+        86_400..604_800 => 0.5,
+        _ => 0.25,
+    };
+    v.rank * weight
+}
+
+fn record_visit(dir: &Path) {
+    let path = dir.to_string_lossy().into_owned();
+    let mut visits = load_visits();
+    let now = unix_secs();
+    match visits.iter_mut().find(|v| v.path == path) {
+        Some(v) => { v.rank += 1.0; v.time = now; }
+        None => visits.push(Visit { path, rank: 1.0, time: now }),
+    }
+    if visits.iter().map(|v| v.rank).sum::<f64>() > MAX_RANK {
+        for v in &mut visits { v.rank *= 0.99; }
+        visits.retain(|v| v.rank >= 1.0);
+    }
+    save_visits(&visits);
+}
+
+/// Whether `path` contains every fragment, in order, ignoring case.
+fn matches_fragments(path: &str, fragments: &[String]) -> bool {
+    let path = path.to_lowercase();
+    let mut from = 0;
+    for f in fragments {
+        let f = f.to_lowercase();
+        match path[from..].find(&f) {
+            Some(i) => from += i + f.len(),
+            None => return false,
+        }
+    }
+    true
+}
+
+/// `path` with the home directory shown as `~`.
+pub fn tilde_path(path: &Path) -> String {
+    match platform::home_dir().and_then(|h| path.strip_prefix(&h).ok().map(Path::to_path_buf)) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".into(),
+        Some(rest) => format!("~{}{}", std::path::MAIN_SEPARATOR, rest.display()),
+        None => path.to_string_lossy().into_owned(),
+    }
+}
+
+impl Shell {
+    /// Change to `dir`, keeping the old directory in `OLDPWD` and
+    /// recording the new one for `z` in interactive sessions.
+    fn change_dir(&mut self, cmd: &str, dir: &Path, out: &mut dyn Sink) -> bool {
+        let old = env::current_dir().ok();
+        if let Err(e) = env::set_current_dir(dir) {
+            self.report(out, &format!("{}: {}: {}", cmd, dir.display(), os_reason(&e)));
+            return false;
+        }
+        if let Some(old) = old { self.vars.insert("OLDPWD".into(), old.to_string_lossy().into_owned()); }
+        if self.interactive && let Ok(new) = env::current_dir() { record_visit(&new); }
+        true
+    }
+
+    /// A relative `dir` that is not in the cwd, found under one of the
+    /// `CDPATH` directories.
+    fn cdpath_lookup(&self, dir: &str) -> Option<PathBuf> {
+        let path = Path::new(dir);
+        if path.is_absolute() || dir.starts_with('.') || path.is_dir() { return None; }
+        let cdpath = self.vars.get("CDPATH").cloned().or_else(|| env::var("CDPATH").ok())?;
+        env::split_paths(&cdpath).filter(|b| !b.as_os_str().is_empty())
+            .map(|b| b.join(path)).find(|c| c.is_dir())
+    }
+
+    /// Resolve a `cd`/`pushd` argument, printing the directory when it
+    /// came from `CDPATH`.
+    fn cd_target(&self, dir: &str, out: &mut dyn Sink) -> PathBuf {
+        match self.cdpath_lookup(dir) {
+            Some(found) => { let _ = write!(out, "{}\n", found.display()); found }
+            None => PathBuf::from(dir),
+        }
+    }
+
+    /// `cd [dir | -]`: no argument goes home, `-` to `OLDPWD`.
+    pub fn builtin_cd(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        let arg = args.join(" ");
+        let dir = if arg.is_empty() {
+            PathBuf::from(platform::home_dir().unwrap_or_else(|| ".".into()))
+        } else if arg == "-" {
+            PathBuf::from(self.vars.get("OLDPWD").cloned().unwrap_or_else(|| ".".into()))
+        } else {
+            self.cd_target(&arg, out)
+        };
+        if self.change_dir("cd", &dir, out) { 0 } else { 1 }
+    }
+
+    /// `pushd dir` changes to `dir` and pushes the old directory on the
+    /// stack; `pushd` swaps the top two; `pushd +n` rotates entry `n` to
+    /// the top.
+    pub fn builtin_pushd(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        let Ok(cwd) = env::current_dir() else { self.report(out, "pushd: cannot read the current directory"); return 1 };
+        let arg = args.join(" ");
+        if arg.is_empty() || arg.starts_with('+') {
+            let n = if arg.is_empty() { 1 } else {
+                match arg[1..].parse::<usize>() {
+                    Ok(n) => n,
+                    Err(_) => { self.report(out, &format!("pushd: {}: invalid number", arg)); return 2; }
+                }
+            };
+            if self.dir_stack.is_empty() { self.report(out, "pushd: no other directory"); return 1; }
+            let mut all = vec![cwd];
+            all.append(&mut self.dir_stack);
+            if n >= all.len() {
+                self.report(out, &format!("pushd: {}: directory stack index out of range", arg));
+                self.dir_stack = all.split_off(1);
+                return 1;
+            }
+            let target = all[n].clone();
+            if !self.change_dir("pushd", &target, out) {
+                self.dir_stack = all.split_off(1);
+                return 1;
+            }
+            // Without a number the top two swap places; `+n` rotates.
+            if arg.is_empty() { all.swap(0, 1); } else { all.rotate_left(n); }
+            self.dir_stack = all.split_off(1);
+        } else {
+            let dir = self.cd_target(&arg, out);
+            if !self.change_dir("pushd", &dir, out) { return 1; }
+            self.dir_stack.insert(0, cwd);
+        }
+        self.print_dirs(false, out);
+        0
+    }
+
+    /// `popd` returns to the directory on top of the stack; `popd +n`
+    /// drops entry `n` instead.
+    pub fn builtin_popd(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        let n = match args.first().map(|a| a.strip_prefix('+').and_then(|n| n.parse::<usize>().ok())) {
+            None => 0,
+            Some(Some(n)) => n,
+            Some(None) => { let _ = out.write_all(b"Usage: popd [+n]\n"); return 2; }
+        };
+        if self.dir_stack.is_empty() { self.report(out, "popd: directory stack empty"); return 1; }
+        if n == 0 {
+            let top = self.dir_stack[0].clone();
+            if !self.change_dir("popd", &top, out) { return 1; }
+            self.dir_stack.remove(0);
+        } else if n <= self.dir_stack.len() {
+            self.dir_stack.remove(n - 1);
+        } else {
+            self.report(out, &format!("popd: +{}: directory stack index out of range", n));
+            return 1;
+        }
+        self.print_dirs(false, out);
+        0
+    }
+
+    /// `dirs [-v | -c]`: show the directory stack, current directory
+    /// first; `-v` numbers the entries, `-c` clears the stack.
+    pub fn builtin_dirs(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        match args.first().map(String::as_str) {
+            None => self.print_dirs(false, out),
+            Some("-v") => self.print_dirs(true, out),
+            Some("-c") => self.dir_stack.clear(),
+            Some(_) => { let _ = out.write_all(b"Usage: dirs [-v | -c]\n"); return 2; }
+        }
+        0
+    }
+
+    fn print_dirs(&self, numbered: bool, out: &mut dyn Sink) {
+        let cwd = env::current_dir().unwrap_or_default();
+        let all: Vec<String> = std::iter::once(&cwd).chain(&self.dir_stack).map(|d| tilde_path(d)).collect();
+        if numbered {
+            for (i, d) in all.iter().enumerate() { let _ = write!(out, "{:>2}  {}\n", i, d); }
+        } else {
+            let _ = write!(out, "{}\n", all.join(" "));
+        }
+    }
+
+    /// `z fragment...` jumps to the most frecent visited directory whose
+    /// path contains the fragments in order; `z -l [fragment...]` and a
+    /// bare `z` list the candidates with their scores, best last.
+    pub fn builtin_z(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        let list = args.first().is_some_and(|a| a == "-l");
+        let fragments = if list { &args[1..] } else { args };
+        let now = unix_secs();
+        let visits = load_visits();
+        let mut found: Vec<&Visit> = visits.iter()
+            .filter(|v| matches_fragments(&v.path, fragments) && Path::new(&v.path).is_dir())
+            .collect();
+        found.sort_by(|a, b| frecency(b, now).total_cmp(&frecency(a, now)));
+        if list || fragments.is_empty() {
+            for v in found.iter().rev() { let _ = write!(out, "{:<10.1} {}\n", frecency(v, now), v.path); }
+            return if found.is_empty() && !fragments.is_empty() { 1 } else { 0 };
+        }
+        let Some(best) = found.first() else {
+            self.report(out, &format!("z: {}: no matching directory", fragments.join(" ")));
+            return 1;
+        };
+        let dir = PathBuf::from(&best.path);
+        if self.change_dir("z", &dir, out) { 0 } else { 1 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragments(list: &[&str]) -> Vec<String> { list.iter().map(|f| f.to_string()).collect() }
+
+    #[test]
+    fn fragment_matching() {
+        let path = "/home/ann/Projects/nes-shell/src";
+        assert!(matches_fragments(path, &fragments(&[])));
+        assert!(matches_fragments(path, &fragments(&["proj", "src"])));
+        assert!(matches_fragments(path, &fragments(&["NES"])));
+        assert!(!matches_fragments(path, &fragments(&["src", "proj"])));
+        assert!(!matches_fragments(path, &fragments(&["shell", "shell"])));
+        assert!(!matches_fragments(path, &fragments(&["docs"])));
+    }
+
+    #[test]
+    fn frecency_ranking() {
+        let now = 10_000_000;
+        let visit = |path: &str, rank: f64, ago: u64| Visit { path: path.into(), rank, time: now - ago };
+        let visits = [visit("/old", 40.0, 30 * 86_400), visit("/hour", 3.0, 60), visit("/day", 5.0, 7200), visit("/week", 10.0, 2 * 86_400)];
+        let scores: Vec<f64> = visits.iter().map(|v| frecency(v, now)).collect();
+        assert_eq!(scores, [10.0, 12.0, 10.0, 5.0]);
+        let mut ranked: Vec<&Visit> = visits.iter().collect();
+        ranked.sort_by(|a, b| frecency(b, now).total_cmp(&frecency(a, now)));
+        assert_eq!(ranked.iter().map(|v| v.path.as_str()).collect::<Vec<_>>(), ["/hour", "/old", "/day", "/week"]);
+    }
+
+    #[test]
+    fn cdpath_resolution() {
+        let root = env::temp_dir().join(format!("nes-cdpath-{}", std::process::id()));
+        let target = format!("nes-cdpath-target-{}", std::process::id());
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b").join(&target)).unwrap();
+        fs::write(root.join("a").join(&target), "").unwrap();
+        let mut sh = Shell::new();
+        let cdpath = env::join_paths([PathBuf::new(), root.join("missing"), root.join("a"), root.join("b")]).unwrap();
+        sh.vars.insert("CDPATH".into(), cdpath.to_string_lossy().into_owned());
+        assert_eq!(sh.cdpath_lookup(&target), Some(root.join("b").join(&target)));
+        assert_eq!(sh.cdpath_lookup(&format!("./{}", target)), None);
+        assert_eq!(sh.cdpath_lookup(&root.join("b").to_string_lossy()), None);
+        assert_eq!(sh.cdpath_lookup("nes-no-such-dir"), None);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,486 @@
+// ══════════════════════════════════════════════════════════════════
+// Line editor — raw-mode input for the interactive prompt
+// ══════════════════════════════════════════════════════════════════
+//
+// Keys are read one at a time with the terminal in raw mode, and the
+// whole buffer is redrawn after each one. The buffer may span several
+// lines: Enter only submits once the input is complete, so an
+// `if … end` block is typed, recalled and edited as a single entry.
+
+use std::io::{self, BufRead, Read, Write};
+
+use crate::{platform, syntax};
+
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Enter,
+    /// Alt-Enter: always a new line, even when the input is complete.
+    Newline,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    KillWordLeft,
+    KillWordRight,
+    Tab,
+    Other,
+}
+
+/// Read one key; `None` at end of input.
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(b) = read_byte(input)? else { return Ok(None) };
+    let key = match b {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => read_escape(input)?,
+        0..0x20 => Key::Ctrl((b + b'`') as char),
+        _ => {
+            // The length of a UTF-8 sequence is given by its first byte.
+            let len = match b { 0xc0..0xe0 => 2, 0xe0..0xf0 => 3, 0xf0.. => 4, _ => 1 };
+            let mut bytes = vec![b];
+            for _ in 1..len {
+                match read_byte(input)? { Some(c) => bytes.push(c), None => break }
+            }
+            String::from_utf8(bytes).ok().and_then(|s| s.chars().next()).map_or(Key::Other, Key::Char)
+        }
+    };
+    Ok(Some(key))
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut b = [0u8];
+    Ok(if input.read(&mut b)? == 0 { None } else { Some(b[0]) })
+}
+
+/// Decode what follows ESC: arrow and editing keys (`ESC [ …`,
+/// `ESC O …`) or Alt + key.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let Some(b) = read_byte(input)? else { return Ok(Key::Other) };
+    let key = match b {
+        b'[' => {
+            let mut params = String::new();
+            loop {
+                match read_byte(input)? {
+                    Some(c @ 0x40..=0x7e) => break csi_key(&params, c),
+                    Some(c) => params.push(c as char),
+                    None => break Key::Other,
+                }
+            }
+        }
+        b'O' => match read_byte(input)? {
+            Some(c) => csi_key("", c),
+            None => Key::Other,
+        },
+        b'b' | b'B' => Key::WordLeft,
+        b'f' | b'F' => Key::WordRight,
+        b'd' | b'D' => Key::KillWordRight,
+        0x7f | 0x08 => Key::KillWordLeft,
+        b'\r' | b'\n' => Key::Newline,
+        _ => Key::Other,
+    };
+    Ok(key)
+}
+
+fn csi_key(params: &str, last: u8) -> Key {
+    // `1;5` (Ctrl) and `1;3` (Alt) turn the arrows into word motions.
+    let word = params.ends_with(";5") || params.ends_with(";3");
+    match (last, params) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => if word { Key::WordRight } else { Key::Right },
+        (b'D', _) => if word { Key::WordLeft } else { Key::Left },
+        (b'H', _) | (b'~', "1" | "7") => Key::Home,
+        (b'F', _) | (b'~', "4" | "8") => Key::End,
+        (b'~', "3") => Key::Delete,
+        _ => Key::Other,
+    }
+}
+
+// ── Drawing ───────────────────────────────────────────────────
+
+/// Output for one redraw, tracking where the terminal cursor ends up.
+/// Lines are broken explicitly at the terminal width so the row count
+/// is always known.
+struct Painter {
+    out: String,
+    width: usize,
+    row: usize,
+    col: usize,
+    escape: bool,
+}
+
+impl Painter {
+    fn put(&mut self, text: &str) {
+        for c in text.chars() { self.put_char(c); }
+    }
+
+    fn put_char(&mut self, c: char) {
+        self.out.push(c);
+        // Colour codes in prompts take no room on screen.
+        if self.escape { self.escape = !c.is_ascii_alphabetic(); return; }
+        if c == '\x1b' { self.escape = true; return; }
+        if c == '\n' { self.row += 1; self.col = 0; return; }
+        self.col += 1;
+        if self.col == self.width {
+            self.out.push_str("\r\n");
+            self.row += 1;
+            self.col = 0;
+        }
+    }
+}
+
+// ── Editor ────────────────────────────────────────────────────
+
+/// Interactive line editor. Keeps the kill buffer between lines.
+#[derive(Default)]
+pub struct Editor {
+    buf: Vec<char>,
+    pos: usize,
+    /// Row of the cursor, counted from the first row of the prompt.
+    row: usize,
+    kill: Vec<char>,
+}
+
+impl Editor {
+    /// Show `prompt` and read input, continuing on lines that start with
+    /// `cont` until `complete` accepts it. `history` is recalled with
+    /// Up/Down and searched with Ctrl-R; Tab asks `completer` for the
+    /// words that could replace the one before the cursor. Returns `None`
+    /// at end of input; Ctrl-C discards the input and returns an empty line.
+    pub fn read(
+        &mut self, prompt: &str, cont: &str, history: &[String], complete: impl Fn(&str) -> bool,
+        completer: impl Fn(&str) -> (usize, Vec<String>),
+    ) -> io::Result<Option<String>> {
+        let Some(_raw) = platform::raw_mode() else { return read_plain(prompt) };
+        let mut input = io::stdin().lock();
+        (self.buf, self.pos, self.row) = (Vec::new(), 0, 0);
+        let mut hist = history.len();
+        let mut saved: Vec<char> = Vec::new();
+        self.draw(prompt, cont)?;
+        let mut pending = None;
+        loop {
+            let key = match pending.take() {
+                Some(k) => k,
+                None => match read_key(&mut input)? { Some(k) => k, None => return Ok(None) },
+            };
+            match key {
+                Key::Enter if complete(&self.text()) => return self.finish(prompt, cont).map(Some),
+                Key::Enter | Key::Newline => self.insert(&['\n']),
+                Key::Char(c) => self.insert(&[c]),
+                Key::Ctrl('c') => {
+                    self.pos = self.buf.len();
+                    self.draw(prompt, cont)?;
+                    self.buf.clear();
+                    write_out("^C\n")?;
+                    return Ok(Some(String::new()));
+                }
+                Key::Ctrl('d') if self.buf.is_empty() => { write_out("\n")?; return Ok(None); }
+                Key::Ctrl('d') | Key::Delete if self.pos < self.buf.len() => { self.buf.remove(self.pos); }
+                Key::Backspace if self.pos > 0 => { self.pos -= 1; self.buf.remove(self.pos); }
+                Key::Left | Key::Ctrl('b') => self.pos = self.pos.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => self.pos = (self.pos + 1).min(self.buf.len()),
+                Key::Home | Key::Ctrl('a') => self.pos = self.line_start(self.pos),
+                Key::End | Key::Ctrl('e') => self.pos = self.line_end(self.pos),
+                Key::WordLeft => self.pos = self.word_left(),
+                Key::WordRight => self.pos = self.word_right(),
+                Key::Ctrl('k') => {
+                    let end = self.line_end(self.pos);
+                    // At the end of a line, join it with the next.
+                    let end = if end == self.pos { (end + 1).min(self.buf.len()) } else { end };
+                    self.cut(self.pos, end);
+                }
+                Key::Ctrl('u') => self.cut(self.line_start(self.pos), self.pos),
+                Key::Ctrl('w') => {
+                    let mut start = self.pos;
+                    while start > 0 && self.buf[start - 1].is_whitespace() { start -= 1; }
+                    while start > 0 && !self.buf[start - 1].is_whitespace() { start -= 1; }
+                    self.cut(start, self.pos);
+                }
+                Key::KillWordLeft => self.cut(self.word_left(), self.pos),
+                Key::KillWordRight => self.cut(self.pos, self.word_right()),
+                Key::Ctrl('y') => { let kill = self.kill.clone(); self.insert(&kill); }
+                Key::Ctrl('l') => { write_out("\x1b[H\x1b[2J")?; self.row = 0; }
+                Key::Up | Key::Ctrl('p') => {
+                    let start = self.line_start(self.pos);
+                    if start > 0 {
+                        let above = self.line_start(start - 1);
+                        self.pos = (above + self.pos - start).min(start - 1);
+                    } else if hist > 0 {
+                        if hist == history.len() { saved = self.buf.clone(); }
+                        hist -= 1;
+                        self.recall(&history[hist]);
+                    }
+                }
+                Key::Down | Key::Ctrl('n') => {
+                    let end = self.line_end(self.pos);
+                    if end < self.buf.len() {
+                        let below = end + 1;
+                        self.pos = (below + self.pos - self.line_start(self.pos)).min(self.line_end(below));
+                    } else if hist < history.len() {
+                        hist += 1;
+                        let text: String = if hist == history.len() { saved.iter().collect() } else { history[hist].clone() };
+                        self.recall(&text);
+                    }
+                }
+                Key::Ctrl('r') => pending = self.search(&mut input, history)?,
+                Key::Tab => {
+                    let before: String = self.buf[..self.pos].iter().collect();
+                    let (start, found) = completer(&before);
+                    self.complete(start, &found, prompt, cont)?;
+                }
+                _ => {}
+            }
+            self.draw(prompt, cont)?;
+        }
+    }
+
+    fn text(&self) -> String { self.buf.iter().collect() }
+
+    /// Replace the word from `start` to the cursor with the only match,
+    /// or with the longest prefix all matches share; when that adds
+    /// nothing, list the matches under the input.
+    fn complete(&mut self, start: usize, found: &[String], prompt: &str, cont: &str) -> io::Result<()> {
+        let Some(first) = found.first() else { return Ok(()) };
+        if found.len() == 1 {
+            let mut word = typed(first);
+            if !first.ends_with('/') { word.push(' '); }
+            self.replace_word(start, &word);
+            return Ok(());
+        }
+        let common = found[1..].iter().fold(first.chars().count(), |n, f| {
+            first.chars().zip(f.chars()).take(n).take_while(|(a, b)| a == b).count()
+        });
+        // The word typed so far, without its quotes.
+        let so_far = self.buf[start..self.pos].iter().filter(|c| !matches!(c, '\'' | '"')).count();
+        if common > so_far {
+            let prefix: String = first.chars().take(common).collect();
+            self.replace_word(start, &typed(&prefix));
+            return Ok(());
+        }
+        // List them below the input, then draw the input again.
+        let pos = self.pos;
+        self.pos = self.buf.len();
+        self.draw(prompt, cont)?;
+        self.pos = pos;
+        write_out(&format!("\n{}", columns(found)))?;
+        self.row = 0;
+        Ok(())
+    }
+
+    fn replace_word(&mut self, start: usize, word: &str) {
+        self.buf.splice(start..self.pos, word.chars());
+        self.pos = start + word.chars().count();
+    }
+
+    /// Redraw the prompt and buffer and put the cursor in place.
+    fn draw(&mut self, prompt: &str, cont: &str) -> io::Result<()> {
+        let mut p = Painter { out: String::new(), width: platform::terminal_width().max(2), row: 0, col: 0, escape: false };
+        if self.row > 0 { p.out.push_str(&format!("\x1b[{}A", self.row)); }
+        p.out.push_str("\r\x1b[J");
+        p.put(prompt);
+        let mut cursor = (p.row, p.col);
+        for (i, &c) in self.buf.iter().enumerate() {
+            if i == self.pos { cursor = (p.row, p.col); }
+            p.put_char(c);
+            if c == '\n' { p.put(cont); }
+        }
+        if self.pos == self.buf.len() { cursor = (p.row, p.col); }
+        if p.row > cursor.0 { p.out.push_str(&format!("\x1b[{}A", p.row - cursor.0)); }
+        p.out.push('\r');
+        if cursor.1 > 0 { p.out.push_str(&format!("\x1b[{}C", cursor.1)); }
+        self.row = cursor.0;
+        write_out(&p.out)
+    }
+
+    /// Draw the final input with the cursor after it and move below.
+    fn finish(&mut self, prompt: &str, cont: &str) -> io::Result<String> {
+        self.pos = self.buf.len();
+        self.draw(prompt, cont)?;
+        write_out("\n")?;
+        Ok(self.text())
+    }
+
+    fn insert(&mut self, chars: &[char]) {
+        self.buf.splice(self.pos..self.pos, chars.iter().copied());
+        self.pos += chars.len();
+    }
+
+    /// Remove `start..end` into the kill buffer.
+    fn cut(&mut self, start: usize, end: usize) {
+        if start >= end { return; }
+        self.kill = self.buf.drain(start..end).collect();
+        self.pos = start;
+    }
+
+    fn recall(&mut self, entry: &str) {
+        self.buf = entry.chars().collect();
+        self.pos = self.buf.len();
+    }
+
+    fn line_start(&self, pos: usize) -> usize {
+        self.buf[..pos].iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1)
+    }
+
+    fn line_end(&self, pos: usize) -> usize {
+        self.buf[pos..].iter().position(|&c| c == '\n').map_or(self.buf.len(), |i| pos + i)
+    }
+
+    fn word_left(&self) -> usize {
+        let mut i = self.pos;
+        while i > 0 && !is_word(self.buf[i - 1]) { i -= 1; }
+        while i > 0 && is_word(self.buf[i - 1]) { i -= 1; }
+        i
+    }
+
+    fn word_right(&self) -> usize {
+        let mut i = self.pos;
+        while i < self.buf.len() && !is_word(self.buf[i]) { i += 1; }
+        while i < self.buf.len() && is_word(self.buf[i]) { i += 1; }
+        i
+    }
+
+    /// Ctrl-R: search the history backwards as the query is typed.
+    /// Ctrl-R again finds an older match, Ctrl-G restores the input, and
+    /// any other key takes the match and is then handled as usual.
+    fn search(&mut self, input: &mut impl Read, history: &[String]) -> io::Result<Option<Key>> {
+        let (original, original_pos) = (self.buf.clone(), self.pos);
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+        let mut failed = false;
+        loop {
+            let label = format!("({}reverse-i-search)`{}': ", if failed { "failed " } else { "" }, query);
+            self.draw(&label, "")?;
+            let Some(key) = read_key(input)? else { return Ok(None) };
+            let from = match key {
+                Key::Char(c) => { query.push(c); found.map_or(history.len(), |i| i + 1) }
+                Key::Backspace => { query.pop(); history.len() }
+                Key::Ctrl('r') => found.unwrap_or(history.len()),
+                Key::Ctrl('g') | Key::Ctrl('c') => {
+                    (self.buf, self.pos) = (original, original_pos);
+                    return Ok(None);
+                }
+                key => return Ok(Some(key)),
+            };
+            let hit = (0..from).rev().find(|&i| history[i].contains(&query));
+            failed = hit.is_none() && !query.is_empty();
+            if let Some(i) = hit.filter(|_| !query.is_empty()) {
+                found = Some(i);
+                self.recall(&history[i]);
+                let at = history[i].find(&query).unwrap_or(0);
+                self.pos = history[i][..at].chars().count();
+            }
+        }
+    }
+}
+
+fn is_word(c: char) -> bool { c.is_alphanumeric() || c == '_' }
+
+/// How a completion is typed on the command line: quoted when the shell
+/// would read it differently, except for a leading `~/` or `~user/`
+/// (which must stay unquoted to expand) and `$name` variables.
+fn typed(candidate: &str) -> String {
+    if candidate.starts_with('$') { return candidate.to_string(); }
+    let split = if candidate.starts_with('~') { candidate.find('/').map_or(0, |i| i + 1) } else { 0 };
+    let (home, rest) = candidate.split_at(split);
+    if rest.is_empty() { return candidate.to_string(); }
+    format!("{}{}", home, syntax::quote(rest))
+}
+
+/// Completion matches laid out in columns, paths shown by their last part.
+fn columns(found: &[String]) -> String {
+    const MAX_LISTED: usize = 200;
+    if found.len() > MAX_LISTED { return format!("{} possibilities; type more to narrow them down\n", found.len()); }
+    let names: Vec<&str> = found.iter().map(|f| {
+        let end = f.trim_end_matches('/').len();
+        &f[f[..end].rfind('/').map_or(0, |i| i + 1)..]
+    }).collect();
+    let width = names.iter().map(|n| n.chars().count()).max().unwrap_or(0) + 2;
+    let per_row = (platform::terminal_width() / width).max(1);
+    let mut text = String::new();
+    for row in names.chunks(per_row) {
+        for name in row { text.push_str(&format!("{:<1$}", name, width)); }
+        text.truncate(text.trim_end().len());
+        text.push('\n');
+    }
+    text
+}
+
+fn write_out(text: &str) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    out.write_all(text.as_bytes())?;
+    out.flush()
+}
+
+/// Fallback when the terminal cannot be put in raw mode.
+fn read_plain(prompt: &str) -> io::Result<Option<String>> {
+    write_out(prompt)?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 { return Ok(None); }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completions_are_quoted() {
+        assert_eq!(typed("src/main.rs"), "src/main.rs");
+        assert_eq!(typed("a&b"), "'a&b'");
+        assert_eq!(typed("x;y"), "'x;y'");
+        assert_eq!(typed("dir/a b/"), "'dir/a b/'");
+        assert_eq!(typed("it's"), "'it'\"'\"'s'");
+        assert_eq!(typed("~/My Files/"), "~/'My Files/'");
+        assert_eq!(typed("~/"), "~/");
+        assert_eq!(typed("${HOME}"), "${HOME}");
+    }
+
+    fn keys(mut bytes: &[u8]) -> Vec<Key> {
+        std::iter::from_fn(|| read_key(&mut bytes).unwrap()).collect()
+    }
+
+    #[test]
+    fn key_decoding() {
+        let decoded = keys("a\u{e9}\x12\t\r\x7f\x1b[A\x1b[1;5C\x1bOH\x1b[3~\x1bb\x1b\x7f\x1b\r\x1b[9z".as_bytes());
+        assert!(matches!(decoded[..], [
+            Key::Char('a'), Key::Char('\u{e9}'), Key::Ctrl('r'), Key::Tab, Key::Enter, Key::Backspace,
+            Key::Up, Key::WordRight, Key::Home, Key::Delete, Key::WordLeft, Key::KillWordLeft, Key::Newline, Key::Other,
+        ]));
+        assert!(matches!(keys(b"\x1b")[..], [Key::Other]));
+    }
+
+    #[test]
+    fn motions() {
+        let mut ed = Editor::default();
+        ed.recall("if true\n    echo some_word, ok");
+        assert_eq!((ed.line_start(ed.pos), ed.line_end(3)), (8, 7));
+        assert_eq!(ed.word_left(), ed.buf.len() - 2);
+        ed.pos = ed.word_left();
+        ed.pos = ed.word_left();
+        assert_eq!(ed.text()[ed.pos..].to_string(), "some_word, ok");
+        assert_eq!(ed.word_right(), ed.buf.len() - 4);
+        ed.cut(ed.pos, ed.word_right());
+        assert_eq!((ed.text(), ed.kill.iter().collect::<String>()), ("if true\n    echo , ok".to_string(), "some_word".to_string()));
+        ed.insert(&['x']);
+        assert!(ed.text().ends_with("echo x, ok") && ed.pos == ed.buf.len() - 4);
+    }
+
+    #[test]
+    fn painting() {
+        let mut p = Painter { out: String::new(), width: 4, row: 0, col: 0, escape: false };
+        p.put("\x1b[32m>\x1b[0m abcde\nxy");
+        assert_eq!((p.row, p.col), (2, 2));
+        assert!(p.out.ends_with("\x1b[0m ab\r\ncde\nxy"), "{:?}", p.out);
+        let listed = columns(&["src/main.rs".into(), "src/deep/".into(), "x".into()]);
+        assert_eq!(listed.split_whitespace().collect::<Vec<_>>(), ["main.rs", "deep/", "x"]);
+        assert!(columns(&vec![String::from("x"); 201]).starts_with("201 possibilities"));
+    }
+}
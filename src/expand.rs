@@ -0,0 +1,634 @@
+// ══════════════════════════════════════════════════════════════════
+// Word expansion — turning parsed NesC words into argument strings
+// ══════════════════════════════════════════════════════════════════
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::syntax::{Word, WordPart};
+use crate::{platform, Shell};
+
+impl Shell {
+    pub fn lookup_var(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.status.to_string()),
+            "0" => Some(self.script.clone()),
+            "#" => Some(self.args.len().to_string()),
+            "!" => self.last_bg.map(|pid| pid.to_string()),
+            "@" => Some(self.args.join(" ")),
+            _ if name.bytes().all(|b| b.is_ascii_digit()) => {
+                let n: usize = name.parse().ok()?;
+                self.args.get(n.checked_sub(1)?).cloned()
+            }
+            _ => self.vars.get(name).cloned().or_else(|| env::var(name).ok()),
+        }
+    }
+
+    /// Run `src` as a command line and return what it printed, without
+    /// trailing newlines — the value of `$(src)`. An `exit` inside only
+    /// ends the substitution, `break` cannot leave it, and the working
+    /// directory is restored.
+    pub fn command_output(&mut self, src: &str) -> String {
+        let cwd = env::current_dir().ok();
+        let saved = (self.running, self.loop_depth);
+        self.loop_depth = 0;
+        let mut buf = Vec::new();
+        self.exec(src, &mut buf);
+        (self.running, self.loop_depth) = saved;
+        if let Some(dir) = cwd { let _ = env::set_current_dir(dir); }
+        let mut text = String::from_utf8_lossy(&buf).into_owned();
+        while text.ends_with('\n') || text.ends_with('\r') { text.pop(); }
+        text
+    }
+
+    /// Expand every word of a command into its argument list.
+    pub fn expand_words(&mut self, words: &[Word]) -> Result<Vec<String>, String> {
+        let mut args = Vec::with_capacity(words.len());
+        for w in words { self.expand_word(w, &mut args)?; }
+        Ok(args)
+    }
+
+    /// Expand one word. Quoted parts always yield exactly one argument;
+    /// unquoted `$var`, `${...}` and `$(...)` values are split on
+    /// whitespace, and unquoted wildcards and `{a,b}` expand to several
+    /// arguments, so a word can produce zero, one or many.
+    pub fn expand_word(&mut self, word: &Word, args: &mut Vec<String>) -> Result<(), String> {
+        let mut cur: Option<Field> = None;
+        for (i, part) in word.iter().enumerate() {
+            match part {
+                WordPart::Lit(s) if i == 0 && let Some((home, rest)) = split_tilde(s, word.len() == 1) => {
+                    let field = cur.get_or_insert_default();
+                    field.push_quoted(&home);
+                    field.push_lit(rest);
+                }
+                WordPart::Lit(s) => cur.get_or_insert_default().push_lit(s),
+                WordPart::Quoted(s) => cur.get_or_insert_default().push_quoted(s),
+                WordPart::Var { name, quoted: true } if name == "@" => {
+                    // "$@": every parameter stays a separate argument.
+                    for (i, arg) in self.args.iter().enumerate() {
+                        if i > 0 && let Some(f) = cur.take() { f.finish(args); }
+                        cur.get_or_insert_default().push_quoted(arg);
+                    }
+                }
+                WordPart::Var { name, quoted } => {
+                    let val = self.lookup_var(name).unwrap_or_default();
+                    push_value(&val, *quoted, &mut cur, args);
+                }
+                WordPart::Param { body, quoted } => {
+                    let val = self.expand_param(body)?;
+                    push_value(&val, *quoted, &mut cur, args);
+                }
+                WordPart::Subst { src, quoted } => {
+                    let val = self.command_output(src);
+                    push_value(&val, *quoted, &mut cur, args);
+                }
+            }
+        }
+        if let Some(f) = cur { f.finish(args); }
+        Ok(())
+    }
+
+    /// Expand one word to a single string, with no splitting or
+    /// wildcards — for values such as condition operands.
+    pub fn expand_text(&mut self, word: &Word) -> Result<String, String> {
+        let mut text = String::new();
+        for (i, part) in word.iter().enumerate() {
+            match part {
+                WordPart::Lit(s) if i == 0 && let Some((home, rest)) = split_tilde(s, word.len() == 1) => {
+                    text.push_str(&home);
+                    text.push_str(rest);
+                }
+                WordPart::Lit(s) | WordPart::Quoted(s) => text.push_str(s),
+                WordPart::Var { name, .. } => text.push_str(&self.lookup_var(name).unwrap_or_default()),
+                WordPart::Param { body, .. } => text.push_str(&self.expand_param(body)?),
+                WordPart::Subst { src, .. } => text.push_str(&self.command_output(src)),
+            }
+        }
+        Ok(text)
+    }
+
+    // ── ${...} ────────────────────────────────────────────────────
+
+    /// Evaluate the text between the braces of `${...}`.
+    pub fn expand_param(&mut self, body: &str) -> Result<String, String> {
+        let bad = || format!("${{{}}}: bad substitution", body);
+        if let Some(name) = body.strip_prefix('#') && !name.is_empty() {
+            if !is_param_name(name) { return Err(bad()); }
+            return Ok(self.lookup_var(name).unwrap_or_default().chars().count().to_string());
+        }
+        let name_len = if body.starts_with(['?', '@', '#']) { 1 } else {
+            body.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(body.len())
+        };
+        if name_len == 0 { return Err(bad()); }
+        let (name, rest) = body.split_at(name_len);
+        let val = self.lookup_var(name);
+        if rest.is_empty() { return Ok(val.unwrap_or_default()); }
+
+        // ${name:-word} ${name-word} and the =, ?, + variants
+        let (colon, op) = match rest.strip_prefix(':') { Some(r) => (true, r), None => (false, rest) };
+        if let Some(kind) = op.chars().next().filter(|c| "-=?+".contains(*c)) {
+            let word = &op[1..];
+            let missing = match &val { None => true, Some(v) => colon && v.is_empty() };
+            return match kind {
+                '-' if missing => self.expand_arg(word),
+                '=' if missing => {
+                    let v = self.expand_arg(word)?;
+                    self.vars.insert(name.to_string(), v.clone());
+                    Ok(v)
+                }
+                '?' if missing => {
+                    let msg = self.expand_arg(word)?;
+                    let msg = if msg.is_empty() { "parameter null or not set".into() } else { msg };
+                    // As in other shells, a script stops here; at the
+                    // prompt the rest of the line is dropped.
+                    if self.interactive { self.interrupted = true; } else { self.running = false; }
+                    Err(format!("{}: {}", name, msg))
+                }
+                '+' if missing => Ok(String::new()),
+                '+' => self.expand_arg(word),
+                _ => Ok(val.unwrap_or_default()),
+            };
+        }
+
+        let val = val.unwrap_or_default();
+        let chars: Vec<char> = val.chars().collect();
+        let n = chars.len();
+
+        // ${name:offset} ${name:offset:length}
+        if colon {
+            let (off, len) = match op.split_once(':') { Some((o, l)) => (o, Some(l)), None => (op, None) };
+            let num = |s: String| s.trim().parse::<i64>().map_err(|_| bad());
+            let total = n as i64;
+            let mut start = num(self.expand_arg(off)?)?;
+            if start < 0 { start += total; }
+            let start = start.clamp(0, total);
+            let end = match len {
+                None => total,
+                Some(l) => {
+                    let l = num(self.expand_arg(l)?)?;
+                    if l < 0 { (total + l).max(start) } else { (start + l).min(total) }
+                }
+            };
+            return Ok(chars[start as usize..end as usize].iter().collect());
+        }
+
+        // ${name#pat} ${name##pat} ${name%pat} ${name%%pat}
+        for (prefix, longest, front) in [("##", true, true), ("#", false, true), ("%%", true, false), ("%", false, false)] {
+            let Some(pat) = op.strip_prefix(prefix) else { continue };
+            let pat: Vec<char> = self.expand_arg(pat)?.chars().collect();
+            // Cut points from the shortest match to the longest.
+            let mut cuts: Vec<usize> = if front { (0..=n).collect() } else { (0..=n).rev().collect() };
+            if longest { cuts.reverse(); }
+            for i in cuts {
+                let (matched, kept) = if front { (&chars[..i], &chars[i..]) } else { (&chars[i..], &chars[..i]) };
+                if wildcard_match(&pat, matched) { return Ok(kept.iter().collect()); }
+            }
+            return Ok(val);
+        }
+
+        // ${name/pat/rep} ${name//pat/rep} ${name/#pat/rep} ${name/%pat/rep}
+        if let Some(spec) = op.strip_prefix('/') {
+            let (mode, spec) = match spec.chars().next() {
+                Some(m @ ('/' | '#' | '%')) => (Some(m), &spec[1..]),
+                _ => (None, spec),
+            };
+            let (pat, rep) = spec.split_once('/').unwrap_or((spec, ""));
+            let pat: Vec<char> = self.expand_arg(pat)?.chars().collect();
+            let rep = self.expand_arg(rep)?;
+            if pat.is_empty() { return Ok(val); }
+            let mut out = String::with_capacity(val.len());
+            let mut i = 0;
+            while i <= n {
+                let found = match mode {
+                    Some('#') if i > 0 => None,
+                    Some('%') => wildcard_match(&pat, &chars[i..]).then_some(n),
+                    _ => (i..=n).rev().find(|&e| wildcard_match(&pat, &chars[i..e])),
+                };
+                match found {
+                    Some(e) if e > i => {
+                        out.push_str(&rep);
+                        i = e;
+                        if mode != Some('/') {
+                            out.extend(&chars[i..]);
+                            return Ok(out);
+                        }
+                    }
+                    _ => {
+                        if i < n { out.push(chars[i]); }
+                        i += 1;
+                    }
+                }
+            }
+            return Ok(out);
+        }
+        Err(bad())
+    }
+
+    /// Expand the word inside `${name:-word}` and friends. Surrounding
+    /// double quotes are dropped; single quotes keep it literal.
+    fn expand_arg(&mut self, word: &str) -> Result<String, String> {
+        let quoted = |q: char| word.len() >= 2 && word.starts_with(q) && word.ends_with(q);
+        if quoted('\'') { return Ok(word[1..word.len() - 1].to_string()); }
+        let word = if quoted('"') { &word[1..word.len() - 1] } else { word };
+        self.expand_vars(word)
+    }
+}
+
+/// Split a leading `~` or `~user` off `lit` as that home directory and
+/// the rest of the text. `whole` says `lit` is the entire word; otherwise
+/// the name must end at a path separator (`~$x` stays as it is).
+fn split_tilde(lit: &str, whole: bool) -> Option<(String, &str)> {
+    let after = lit.strip_prefix('~')?;
+    let end = after.find(['/', std::path::MAIN_SEPARATOR]);
+    if end.is_none() && !whole { return None; }
+    let (name, rest) = after.split_at(end.unwrap_or(after.len()));
+    let home = if name.is_empty() { platform::home_dir()? } else { platform::user_home(name)? };
+    Some((home, rest))
+}
+
+/// Expand a leading `~` or `~user` in a path typed by the user.
+pub fn expand_tilde(path: &str) -> String {
+    match split_tilde(path, true) {
+        Some((home, rest)) => home + rest,
+        None => path.to_string(),
+    }
+}
+
+fn is_param_name(name: &str) -> bool {
+    matches!(name, "?" | "@" | "#" | "!") || name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// An argument being assembled from word parts. `pattern` mirrors `text`
+/// with everything that came from quotes or expansions escaped, so only
+/// wildcards typed literally are active.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+    glob: bool,
+}
+
+impl Field {
+    fn push_lit(&mut self, s: &str) {
+        self.text.push_str(s);
+        // Backslash is the escape character in patterns, so Windows
+        // separators are turned into forward slashes.
+        if cfg!(windows) { self.pattern.push_str(&s.replace('\\', "/")); } else { self.pattern.push_str(s); }
+        self.glob |= s.contains(['*', '?', '[', '{']);
+    }
+
+    fn push_quoted(&mut self, s: &str) {
+        self.text.push_str(s);
+        for c in s.chars() {
+            if "*?[]{},\\".contains(c) { self.pattern.push('\\'); }
+            self.pattern.push(c);
+        }
+    }
+
+    /// Emit the finished argument, or its brace and file-name expansions.
+    /// A pattern that matches no files is kept as written.
+    fn finish(self, args: &mut Vec<String>) {
+        let alts = if self.glob { brace_expand(&self.pattern) } else { Vec::new() };
+        if alts.len() <= 1 && alts.first().is_none_or(|p| !has_meta(p)) {
+            args.push(self.text);
+            return;
+        }
+        for pat in alts {
+            let matches = if has_meta(&pat) { glob(&pat) } else { Vec::new() };
+            if matches.is_empty() { args.push(unescape(&pat)); } else { args.extend(matches); }
+        }
+    }
+}
+
+/// Append an expanded value to the word being built, splitting it into
+/// separate arguments on whitespace unless it was quoted.
+fn push_value(val: &str, quoted: bool, cur: &mut Option<Field>, args: &mut Vec<String>) {
+    if quoted {
+        cur.get_or_insert_default().push_quoted(val);
+        return;
+    }
+    if val.starts_with(char::is_whitespace) && let Some(f) = cur.take() { f.finish(args); }
+    for (i, word) in val.split_whitespace().enumerate() {
+        if i > 0 && let Some(f) = cur.take() { f.finish(args); }
+        cur.get_or_insert_default().push_quoted(word);
+    }
+    if val.ends_with(char::is_whitespace) && let Some(f) = cur.take() { f.finish(args); }
+}
+
+// ── Wildcard patterns ─────────────────────────────────────────
+
+/// Match `text` against a shell pattern: `*`, `?`, `[abc]`, `[a-z]`,
+/// `[!abc]` and `\` to escape the next character.
+pub fn wildcard_match(pat: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if pat.get(p) == Some(&'*') {
+            star = Some((p, t));
+            p += 1;
+            continue;
+        }
+        if p < pat.len() && let Some(next) = match_one(pat, p, text[t]) {
+            p = next;
+            t += 1;
+            continue;
+        }
+        match star {
+            Some((sp, st)) => {
+                p = sp + 1;
+                t = st + 1;
+                star = Some((sp, st + 1));
+            }
+            None => return false,
+        }
+    }
+    while pat.get(p) == Some(&'*') { p += 1; }
+    p == pat.len()
+}
+
+/// If the pattern element at `p` matches `c`, the index of the next element.
+fn match_one(pat: &[char], p: usize, c: char) -> Option<usize> {
+    match pat[p] {
+        '?' => Some(p + 1),
+        '\\' if p + 1 < pat.len() => (pat[p + 1] == c).then_some(p + 2),
+        '[' => match class_end(pat, p) {
+            Some(end) => class_matches(&pat[p + 1..end], c).then_some(end + 1),
+            None => (c == '[').then_some(p + 1),
+        },
+        lit => (lit == c).then_some(p + 1),
+    }
+}
+
+/// Index of the `]` closing the bracket class that starts at `p`.
+fn class_end(pat: &[char], p: usize) -> Option<usize> {
+    let mut i = p + 1;
+    if matches!(pat.get(i), Some('!' | '^')) { i += 1; }
+    if pat.get(i) == Some(&']') { i += 1; }
+    (i..pat.len()).find(|&j| pat[j] == ']')
+}
+
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negate, class) = match class.first() {
+        Some('!' | '^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut hit = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            hit |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            hit |= class[i] == c;
+            i += 1;
+        }
+    }
+    hit != negate
+}
+
+/// Whether a pattern has an unescaped `*`, `?` or `[`.
+fn has_meta(pat: &str) -> bool {
+    let mut chars = pat.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { chars.next(); }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn unescape(pat: &str) -> String {
+    let mut out = String::with_capacity(pat.len());
+    let mut chars = pat.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' && let Some(n) = chars.next() { out.push(n); } else { out.push(c); }
+    }
+    out
+}
+
+/// Expand the first `{a,b,...}` group in a pattern, recursively. Groups
+/// without a comma are left alone.
+fn brace_expand(pat: &str) -> Vec<String> {
+    let chars: Vec<char> = pat.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => {
+                let mut depth = 0;
+                let mut commas = Vec::new();
+                let mut close = None;
+                let mut j = i;
+                while j < chars.len() {
+                    match chars[j] {
+                        '\\' => j += 1,
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 { close = Some(j); break; }
+                        }
+                        ',' if depth == 1 => commas.push(j),
+                        _ => {}
+                    }
+                    j += 1;
+                }
+                if let Some(close) = close && !commas.is_empty() {
+                    let pre: String = chars[..i].iter().collect();
+                    let post: String = chars[close + 1..].iter().collect();
+                    let mut out = Vec::new();
+                    let mut start = i + 1;
+                    for end in commas.into_iter().chain([close]) {
+                        let alt: String = chars[start..end].iter().collect();
+                        out.extend(brace_expand(&format!("{}{}{}", pre, alt, post)));
+                        start = end + 1;
+                    }
+                    return out;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    vec![pat.to_string()]
+}
+
+/// Paths matching a wildcard pattern, sorted. `**` matches any number of
+/// directories. Hidden entries only match a pattern that starts with `.`.
+fn glob(pattern: &str) -> Vec<String> {
+    let (base, rest) = match pattern.strip_prefix('/') {
+        Some(r) => ("/", r),
+        None => ("", pattern),
+    };
+    let parts: Vec<&str> = rest.split('/').filter(|p| !p.is_empty()).collect();
+    let mut found = Vec::new();
+    glob_walk(base, &parts, &mut found);
+    found.sort();
+    found.dedup();
+    found
+}
+
+fn glob_walk(base: &str, parts: &[&str], found: &mut Vec<String>) {
+    let Some((first, rest)) = parts.split_first() else {
+        if !base.is_empty() { found.push(base.to_string()); }
+        return;
+    };
+    let join = |name: &str| match base {
+        "" => name.to_string(),
+        b if b.ends_with('/') => format!("{}{}", b, name),
+        b => format!("{}/{}", b, name),
+    };
+    if !has_meta(first) {
+        let path = join(&unescape(first));
+        if fs::symlink_metadata(&path).is_ok() { glob_walk(&path, rest, found); }
+        return;
+    }
+    let dir = if base.is_empty() { "." } else { base };
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let pat: Vec<char> = first.chars().collect();
+    let dotted = first.starts_with('.');
+    for e in entries.flatten() {
+        let name = e.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') && !dotted { continue; }
+        let path = join(&name);
+        let is_dir = e.file_type().is_ok_and(|t| t.is_dir());
+        if *first == "**" {
+            if rest.is_empty() { found.push(path.clone()); }
+            if is_dir { glob_walk(&path, parts, found); }
+        } else if wildcard_match(&pat, &name.chars().collect::<Vec<_>>()) && (rest.is_empty() || is_dir || Path::new(&path).is_dir()) {
+            glob_walk(&path, rest, found);
+        }
+    }
+    if *first == "**" && !rest.is_empty() { glob_walk(base, rest, found); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parse_words;
+
+    fn shell() -> Shell {
+        let mut sh = Shell::new();
+        sh.vars.insert("path".into(), "/usr/lib/nes.tar.gz".into());
+        sh.vars.insert("empty".into(), String::new());
+        sh.vars.insert("two".into(), "a  b".into());
+        sh
+    }
+
+    fn expand(sh: &mut Shell, src: &str) -> Result<Vec<String>, String> {
+        sh.expand_words(&parse_words(src, 1).unwrap())
+    }
+
+    fn param(sh: &mut Shell, body: &str) -> String {
+        sh.expand_param(body).unwrap_or_else(|e| panic!("${{{}}}: {}", body, e))
+    }
+
+    #[test]
+    fn splitting_and_quoting() {
+        let mut sh = shell();
+        assert_eq!(expand(&mut sh, "$two \"$two\" x$empty ${empty}").unwrap(), ["a", "b", "a  b", "x"]);
+        assert_eq!(expand(&mut sh, "\"\" '*' \"${two}\"").unwrap(), ["", "*", "a  b"]);
+        assert_eq!(expand(&mut sh, "$(echo one two)").unwrap(), ["one", "two"]);
+    }
+
+    #[test]
+    fn defaults() {
+        let mut sh = shell();
+        assert_eq!(param(&mut sh, "unset_x:-d"), "d");
+        assert_eq!(param(&mut sh, "empty:-d"), "d");
+        assert_eq!(param(&mut sh, "empty-d"), "");
+        assert_eq!(param(&mut sh, "unset_x-\"a b\""), "a b");
+        assert_eq!(param(&mut sh, "unset_x:='$v'"), "$v");
+        assert_eq!(sh.vars["unset_x"], "$v");
+        assert_eq!(param(&mut sh, "path:+set"), "set");
+        assert_eq!(param(&mut sh, "empty:+set"), "");
+        assert_eq!(param(&mut sh, "#path"), "19");
+        assert!(sh.expand_param("#no-name").is_err());
+    }
+
+    #[test]
+    fn failed_check_stops_script() {
+        let mut sh = shell();
+        assert_eq!(param(&mut sh, "path:?missing"), "/usr/lib/nes.tar.gz");
+        assert!(sh.running);
+        assert_eq!(sh.expand_param("unset_y:?missing"), Err("unset_y: missing".to_string()));
+        assert!(!sh.running);
+        let mut sh = shell();
+        sh.interactive = true;
+        assert_eq!(sh.expand_param("empty:?"), Err("empty: parameter null or not set".to_string()));
+        assert!(sh.running && sh.interrupted);
+    }
+
+    #[test]
+    fn trimming_and_substrings() {
+        let mut sh = shell();
+        assert_eq!(param(&mut sh, "path#*/"), "usr/lib/nes.tar.gz");
+        assert_eq!(param(&mut sh, "path##*/"), "nes.tar.gz");
+        assert_eq!(param(&mut sh, "path%.*"), "/usr/lib/nes.tar");
+        assert_eq!(param(&mut sh, "path%%.*"), "/usr/lib/nes");
+        assert_eq!(param(&mut sh, "path%.zip"), "/usr/lib/nes.tar.gz");
+        assert_eq!(param(&mut sh, "path:5"), "lib/nes.tar.gz");
+        assert_eq!(param(&mut sh, "path:1:3"), "usr");
+        assert_eq!(param(&mut sh, "path: -2"), "gz");
+        assert_eq!(param(&mut sh, "path: -6:-3"), "tar");
+        assert_eq!(param(&mut sh, "path:100"), "");
+        assert!(sh.expand_param("path:x").is_err());
+    }
+
+    #[test]
+    fn replacement() {
+        let mut sh = shell();
+        assert_eq!(param(&mut sh, "path/./-"), "/usr/lib/nes-tar.gz");
+        assert_eq!(param(&mut sh, "path//./-"), "/usr/lib/nes-tar-gz");
+        assert_eq!(param(&mut sh, "path/#?usr/~"), "~/lib/nes.tar.gz");
+        assert_eq!(param(&mut sh, "path/%gz/bz2"), "/usr/lib/nes.tar.bz2");
+        assert_eq!(param(&mut sh, "path//[ae]"), "/usr/lib/ns.tr.gz");
+        assert_eq!(param(&mut sh, "path/l*b/X"), "/usr/X/nes.tar.gz");
+    }
+
+    #[test]
+    fn braces() {
+        assert_eq!(brace_expand("a{b,c}d"), ["abd", "acd"]);
+        assert_eq!(brace_expand("{x,y{1,2}}z"), ["xz", "y1z", "y2z"]);
+        assert_eq!(brace_expand("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+        assert_eq!(brace_expand("{solo}"), ["{solo}"]);
+        assert_eq!(brace_expand("\\{a,b}"), ["\\{a,b}"]);
+        let mut sh = shell();
+        assert_eq!(expand(&mut sh, "x{a,\"b,c\"} '{p,q}'").unwrap(), ["xa", "xb,c", "{p,q}"]);
+    }
+
+    #[test]
+    fn wildcards() {
+        let m = |p: &str, t: &str| wildcard_match(&p.chars().collect::<Vec<_>>(), &t.chars().collect::<Vec<_>>());
+        assert!(m("*.rs", "main.rs"));
+        assert!(!m("*.rs", "main.rsx"));
+        assert!(m("a?c", "abc"));
+        assert!(m("[a-c]x", "bx"));
+        assert!(!m("[!a-c]x", "bx"));
+        assert!(m("\\*", "*"));
+        assert!(!m("\\*", "a"));
+        assert!(m("*a*a*a*", &"a".repeat(100)));
+        assert!(!m("*a*a*b", &"a".repeat(100)));
+    }
+
+    #[test]
+    fn globbing() {
+        let root = env::temp_dir().join(format!("nes-glob-{}", std::process::id()));
+        for dir in ["src/deep/er", "docs"] { fs::create_dir_all(root.join(dir)).unwrap(); }
+        for file in ["top.rs", "src/a.rs", "src/b.txt", "src/deep/er/c.rs", "src/.hidden.rs", "docs/d.md"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let base = root.to_string_lossy().replace('\\', "/");
+        let rel = |found: Vec<String>| -> Vec<String> {
+            found.into_iter().map(|f| f[base.len() + 1..].to_string()).collect()
+        };
+        let found = rel(glob(&format!("{}/**/*.rs", base)));
+        assert_eq!(found, ["src/a.rs", "src/deep/er/c.rs", "top.rs"]);
+        assert_eq!(rel(glob(&format!("{}/src/*", base))), ["src/a.rs", "src/b.txt", "src/deep"]);
+        assert_eq!(rel(glob(&format!("{}/src/.*.rs", base))), ["src/.hidden.rs"]);
+        assert!(glob(&format!("{}/*.none", base)).is_empty());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,198 @@
+// ══════════════════════════════════════════════════════════════════
+// History — interactive command history, its file and `!` expansion
+// ══════════════════════════════════════════════════════════════════
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::pipeline::Sink;
+use crate::{platform, Shell};
+
+/// Entries kept when `NES_HISTSIZE` is not set.
+const DEFAULT_SIZE: usize = 1000;
+
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    /// File the history is loaded from and appended to; `None` keeps it
+    /// in memory only (scripts and single commands).
+    file: Option<PathBuf>,
+    limit: usize,
+}
+
+impl History {
+    /// Load `NES_HISTFILE`, or `history` in the config directory.
+    pub fn load() -> History {
+        let file = env::var_os("NES_HISTFILE").filter(|f| !f.is_empty()).map(PathBuf::from)
+            .or_else(|| platform::config_dir().map(|d| d.join("history")));
+        let limit = env::var("NES_HISTSIZE").ok().and_then(|n| n.parse().ok()).unwrap_or(DEFAULT_SIZE);
+        let mut history = History { entries: Vec::new(), file, limit };
+        let Some(text) = history.file.as_ref().and_then(|f| fs::read_to_string(f).ok()) else { return history };
+        // Further lines of a multi-line entry (an `if … end` block) are
+        // stored indented by a tab.
+        let mut entries: Vec<String> = Vec::new();
+        for line in text.lines() {
+            match (line.strip_prefix('\t'), entries.last_mut()) {
+                (Some(more), Some(last)) => { last.push('\n'); last.push_str(more); }
+                _ => entries.push(line.to_string()),
+            }
+        }
+        for entry in &entries { history.remember(entry); }
+        // Sessions only append to the file; compact it once it holds
+        // duplicates or more than the limit.
+        if history.entries.len() != entries.len() { history.save(); }
+        history
+    }
+
+    pub fn entries(&self) -> &[String] { &self.entries }
+
+    /// Add `line` in memory, dropping an older copy of it and the oldest
+    /// entries beyond the limit.
+    fn remember(&mut self, line: &str) {
+        if line.trim().is_empty() { return; }
+        self.entries.retain(|e| e != line);
+        self.entries.push(line.to_string());
+        let extra = self.entries.len().saturating_sub(self.limit);
+        self.entries.drain(..extra);
+    }
+
+    /// Record a command line and append it to the history file.
+    pub fn add(&mut self, line: &str) {
+        self.remember(line);
+        let Some(path) = &self.file else { return };
+        if let Some(dir) = path.parent() { let _ = fs::create_dir_all(dir); }
+        if let Ok(mut f) = OpenOptions::new().create(true).append(true).open(path) {
+            let _ = write!(f, "{}\n", line.replace('\n', "\n\t"));
+        }
+    }
+
+    /// Rewrite the file with exactly the entries in memory.
+    fn save(&self) {
+        let Some(path) = &self.file else { return };
+        if let Some(dir) = path.parent() { let _ = fs::create_dir_all(dir); }
+        let mut text = self.entries.iter().map(|e| e.replace('\n', "\n\t")).collect::<Vec<_>>().join("\n");
+        if !text.is_empty() { text.push('\n'); }
+        let _ = fs::write(path, text);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.save();
+    }
+
+    /// Expand `!!` (last command), `!n` (entry n), `!-n` (n commands
+    /// back) and `!prefix` (latest command starting with it). Returns
+    /// `None` when the line has none. A `!` before a space, `=` or `(`,
+    /// after `$`, or inside single quotes stays as it is.
+    pub fn expand(&self, line: &str) -> Result<Option<String>, String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut result = String::with_capacity(line.len());
+        let (mut expanded, mut single, mut double) = (false, false, false);
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\'' if !double => single = !single,
+                '"' if !single => double = !double,
+                _ => {}
+            }
+            let literal = c != '!' || single || (i > 0 && chars[i - 1] == '$')
+                || chars.get(i + 1).is_none_or(|&n| n.is_whitespace() || "=(\"".contains(n));
+            if literal {
+                result.push(c);
+                i += 1;
+                continue;
+            }
+            let end = if chars[i + 1] == '!' {
+                i + 2
+            } else {
+                (i + 1..chars.len()).find(|&j| chars[j].is_whitespace() || ";|&<>()'\"".contains(chars[j]))
+                    .unwrap_or(chars.len())
+            };
+            let designator: String = chars[i + 1..end].iter().collect();
+            let found = match designator.parse::<i64>() {
+                _ if designator == "!" => self.entries.last(),
+                Ok(n) if n > 0 => self.entries.get(n as usize - 1),
+                Ok(n) if n < 0 => self.entries.len().checked_sub(n.unsigned_abs() as usize).map(|k| &self.entries[k]),
+                Ok(_) => None,
+                Err(_) => self.entries.iter().rev().find(|e| e.starts_with(designator.as_str())),
+            };
+            match found {
+                Some(event) => result.push_str(event),
+                None => return Err(format!("!{}: event not found", designator)),
+            }
+            expanded = true;
+            i = end;
+        }
+        Ok(expanded.then_some(result))
+    }
+}
+
+impl Shell {
+    /// `history [n]`, `history -c`, `history search <text>`.
+    pub fn builtin_history(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        let entries = self.history.entries();
+        let listed: Vec<(usize, &String)> = match args.first().map(String::as_str) {
+            None => entries.iter().enumerate().collect(),
+            Some("-c") if args.len() == 1 => { self.history.clear(); return 0; }
+            Some("search") if args.len() > 1 => {
+                let needle = args[1..].join(" ").to_lowercase();
+                entries.iter().enumerate().filter(|(_, e)| e.to_lowercase().contains(&needle)).collect()
+            }
+            Some(n) if args.len() == 1 && let Ok(n) = n.parse::<usize>() => {
+                entries.iter().enumerate().skip(entries.len().saturating_sub(n)).collect()
+            }
+            _ => {
+                let _ = out.write_all(b"Usage: history [n | -c | search <text>]\n");
+                return 2;
+            }
+        };
+        let found = !listed.is_empty();
+        for (i, h) in listed {
+            let _ = write!(out, "  {} {}\n", i + 1, h.replace('\n', "\n    "));
+        }
+        if !found && args.first().is_some_and(|a| a == "search") { return 1; }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+
+    fn history() -> History {
+        let mut h = History { limit: 100, ..History::default() };
+        for line in ["ls -l", "echo one", "git status", "echo two"] { h.remember(line); }
+        h
+    }
+
+    #[test]
+    fn events() {
+        let h = history();
+        assert_eq!(h.expand("!!").unwrap().as_deref(), Some("echo two"));
+        assert_eq!(h.expand("sudo !!").unwrap().as_deref(), Some("sudo echo two"));
+        assert_eq!(h.expand("!1 && !-2").unwrap().as_deref(), Some("ls -l && git status"));
+        assert_eq!(h.expand("!ec; !git").unwrap().as_deref(), Some("echo two; git status"));
+        assert_eq!(h.expand("echo \"!!\"").unwrap().as_deref(), Some("echo \"echo two\""));
+    }
+
+    #[test]
+    fn literal_bangs() {
+        let h = history();
+        for line in ["echo hi", "echo '!!'", "[ a != b ]", "echo wow!", "echo $!", "echo ! x", "echo !(x)"] {
+            assert_eq!(h.expand(line).unwrap(), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn missing_events() {
+        let h = history();
+        assert_eq!(h.expand("!nope"), Err("!nope: event not found".to_string()));
+        assert!(h.expand("!9").is_err());
+        assert!(h.expand("!-9").is_err());
+        assert!(h.expand("!0").is_err());
+        assert!(History::default().expand("!!").is_err());
+    }
+}
@@ -0,0 +1,382 @@
+// ══════════════════════════════════════════════════════════════════
+// Jobs — pipelines started with `&` and the built-ins that manage them
+// ══════════════════════════════════════════════════════════════════
+
+use std::env;
+use std::io::{self, BufReader, Cursor, IsTerminal};
+use std::mem;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+
+use crate::pipeline::{self, Input, Sink};
+use crate::platform::{self, Wait};
+use crate::syntax::{self, Pipeline, Redirect};
+use crate::{os_reason, Shell, BUILTINS};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(i32),
+}
+
+/// A pipeline started with `&`. Its processes share one process group,
+/// led by the first.
+pub struct BgJob {
+    pub id: usize,
+    /// The command line, for `jobs` and notifications.
+    pub cmd: String,
+    procs: Vec<Child>,
+    /// Exit status of each process once it has finished.
+    codes: Vec<Option<i32>>,
+    pub state: JobState,
+}
+
+impl BgJob {
+    fn pgid(&self) -> u32 { self.procs[0].id() }
+
+    /// Collect processes that exited or stopped; with `block`, wait
+    /// until the job is done or stopped. Returns whether the state changed.
+    fn poll(&mut self, block: bool) -> bool {
+        let before = self.state;
+        let mut stopped = false;
+        for (child, code) in self.procs.iter_mut().zip(&mut self.codes) {
+            if code.is_some() { continue; }
+            match platform::wait_child(child, block) {
+                Some(Wait::Exited(c)) => *code = Some(c),
+                Some(Wait::Stopped) => stopped = true,
+                None => {}
+            }
+        }
+        if self.codes.iter().all(Option::is_some) {
+            self.state = JobState::Done(self.codes.last().copied().flatten().unwrap_or(0));
+        } else if stopped {
+            self.state = JobState::Stopped;
+        }
+        self.state != before
+    }
+
+    /// Wait until the job is done or stopped, or Ctrl-C is pressed.
+    fn wait_interruptibly(&mut self) {
+        while self.state == JobState::Running && !platform::interrupt_pending() {
+            if !self.poll(false) { thread::sleep(Duration::from_millis(20)); }
+        }
+    }
+
+    fn send(&self, sig: i32) -> io::Result<()> {
+        if cfg!(unix) { return platform::send_signal(self.pgid(), sig, true); }
+        for child in &self.procs { platform::send_signal(child.id(), sig, false)?; }
+        Ok(())
+    }
+
+    /// Send `sig` to every process of the job. A stopped job only acts
+    /// on it once continued, so it is continued as well.
+    fn signal(&mut self, sig: i32) -> io::Result<()> {
+        self.send(sig)?;
+        if self.state == JobState::Stopped && !matches!(platform::signal_name(sig), Some("STOP" | "TSTP")) {
+            if let Some(cont) = platform::signal_number("CONT") && cont != sig { self.send(cont)?; }
+            self.state = JobState::Running;
+        }
+        Ok(())
+    }
+}
+
+/// Status a shell reports for a job that stopped in the foreground.
+pub fn stopped_status() -> i32 { 128 + platform::signal_number("TSTP").unwrap_or(20) }
+
+/// A pipeline as `jobs` shows it, each word quoted as needed.
+fn command_text<'a>(stages: impl Iterator<Item = &'a [String]>) -> String {
+    stages.map(|argv| argv.iter().map(|a| syntax::quote(a)).collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+impl Shell {
+    /// Whether foreground programs get job control: interactive sessions
+    /// on a terminal, on Unix.
+    pub fn job_control(&self) -> bool {
+        cfg!(unix) && self.interactive && io::stdin().is_terminal()
+    }
+
+    /// Wait for the programs of a foreground pipeline and return how the
+    /// last one ended. With job control they hold the terminal until then;
+    /// if Ctrl-Z stops them, they join the job table as a stopped job for
+    /// `fg` and `bg`. One killed by Ctrl-C interrupts the shell as well,
+    /// since the key never reached it.
+    pub fn wait_foreground(&mut self, stages: &[&[String]], jobs: Vec<pipeline::Job>, control: bool, out: &mut dyn Sink) -> Wait {
+        if !control {
+            let codes: Vec<i32> = jobs.into_iter().map(|j| j.wait().map(platform::exit_code).unwrap_or(127)).collect();
+            return Wait::Exited(codes.last().copied().unwrap_or(0));
+        }
+        let mut jobs = jobs;
+        let codes: Vec<Option<i32>> = jobs.iter_mut().map(|j| match platform::wait_child(&mut j.child, true) {
+            Some(Wait::Exited(code)) => Some(code),
+            _ => None,
+        }).collect();
+        platform::give_terminal(None);
+        if codes.iter().any(Option::is_none) {
+            // A feeder left running carries on when the job is continued.
+            let procs = jobs.into_iter().map(|j| j.child).collect();
+            let id = self.jobs.last().map_or(1, |j| j.id + 1);
+            let cmd = command_text(stages.iter().copied().filter(|a| !a.is_empty()));
+            self.jobs.push(BgJob { id, cmd, procs, codes, state: JobState::Stopped });
+            let line = self.job_line(self.jobs.len() - 1);
+            let _ = out.write_all(format!("\n{}", line).as_bytes());
+            return Wait::Stopped;
+        }
+        for job in jobs { job.finish(); }
+        let code = codes.last().copied().flatten().unwrap_or(0);
+        if code == 128 + 2 { platform::raise_interrupt(); }
+        Wait::Exited(code)
+    }
+
+    /// Start a pipeline in the background and add it to the job table.
+    pub fn exec_background(&mut self, pipeline: &Pipeline, out: &mut dyn Sink) -> i32 {
+        let mut stages = Vec::with_capacity(pipeline.len());
+        for cmd in pipeline {
+            match self.expand_words(&cmd.words) {
+                Ok(argv) if argv.is_empty() => {}
+                Ok(argv) => stages.push((argv, cmd.redirects.as_slice())),
+                Err(msg) => { self.report(out, &msg); return 1; }
+            }
+        }
+        if stages.is_empty() { return 0; }
+        // Built-ins run in a child `nes`, but functions and aliases only
+        // exist in this one.
+        if let Some((argv, _)) = stages.iter().find(|(a, _)| !BUILTINS.contains(&a[0].as_str()) && self.is_builtin(&a[0])) {
+            self.report(out, &format!("{}: functions and aliases cannot run in the background", argv[0]));
+            return 1;
+        }
+        let cmd = command_text(stages.iter().map(|(argv, _)| argv.as_slice()));
+        let mut input = self.input.take();
+        let mut procs: Vec<Child> = Vec::with_capacity(stages.len());
+        for (i, (argv, redirects)) in stages.iter().enumerate() {
+            let last = i + 1 == stages.len();
+            let pgid = procs.first().map_or(0, Child::id);
+            match self.spawn_stage(argv, redirects, input, last, pgid, out) {
+                Some((child, next)) => { procs.push(child); input = next; }
+                None => {
+                    for c in &mut procs { let _ = c.kill(); let _ = c.wait(); }
+                    return 1;
+                }
+            }
+        }
+        let id = self.jobs.last().map_or(1, |j| j.id + 1);
+        let pid = procs[procs.len() - 1].id();
+        self.last_bg = Some(pid);
+        if self.interactive { self.write_err(out, &format!("[{}] {}\n", id, pid)); }
+        let codes = vec![None; procs.len()];
+        self.jobs.push(BgJob { id, cmd, procs, codes, state: JobState::Running });
+        0
+    }
+
+    /// Spawn one stage of a background pipeline in process group `pgid`
+    /// (0 for a new one) and return it with the input for the next stage.
+    fn spawn_stage(
+        &mut self, argv: &[String], redirects: &[Redirect], input: Input, last: bool, pgid: u32, out: &mut dyn Sink,
+    ) -> Option<(Child, Input)> {
+        let redirs = self.open_redirects(redirects, out)?;
+        let saved = redirs.err.map(|e| mem::replace(&mut self.err, e));
+        let input = redirs.input.unwrap_or(input);
+        let wiring = match (redirs.out, last) {
+            (Some(mut file), _) => self.output_stdio(Some(&mut file)),
+            (None, true) => self.output_stdio(Some(&mut *out)),
+            (None, false) => self.output_stdio(None),
+        };
+        if let Some(e) = saved { self.err = e; }
+        let mut cmd = if BUILTINS.contains(&argv[0].as_str()) {
+            let mut c = Command::new(env::current_exe().unwrap_or_else(|_| "nes".into()));
+            c.args(argv.iter().map(|a| syntax::quote(a)));
+            c
+        } else {
+            pipeline::command(argv)
+        };
+        platform::set_process_group(&mut cmd, pgid, false);
+        let started = wiring.and_then(|(o, e, r)| Ok((pipeline::spawn_command(cmd, input, o, e)?, r)));
+        let (job, reader) = match started {
+            Ok(s) => s,
+            Err(e) => { self.report(out, &pipeline::spawn_failure(&argv[0], &e).0); return None; }
+        };
+        let next = match reader {
+            // Output that cannot take a handle (inside `$(...)`) goes to
+            // the terminal instead.
+            Some(r) if last => {
+                thread::spawn(move || { let _ = io::copy(&mut BufReader::new(r), &mut io::stdout()); });
+                Input::Bytes(Cursor::default())
+            }
+            Some(r) => Input::Pipe(BufReader::new(r)),
+            None => Input::Bytes(Cursor::default()),
+        };
+        Some((job.child, next))
+    }
+
+    fn job_line(&self, idx: usize) -> String {
+        let job = &self.jobs[idx];
+        let mark = if idx + 1 == self.jobs.len() { '+' } else if idx + 2 == self.jobs.len() { '-' } else { ' ' };
+        let state = match job.state {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped => "Stopped".to_string(),
+            JobState::Done(0) => "Done".to_string(),
+            JobState::Done(code) => format!("Exit {}", code),
+        };
+        format!("[{}]{}  {:<10}{}\n", job.id, mark, state, job.cmd)
+    }
+
+    /// Print jobs that finished or stopped since the last check and drop
+    /// the finished ones. Called before each interactive prompt.
+    pub fn notify_jobs(&mut self, out: &mut dyn Sink) {
+        for i in 0..self.jobs.len() {
+            if self.jobs[i].poll(false) { let _ = out.write_all(self.job_line(i).as_bytes()); }
+        }
+        self.jobs.retain(|j| !matches!(j.state, JobState::Done(_)));
+    }
+
+    /// Index of the job named by `%n`, `%+` / `%%` (current, the default)
+    /// or `%-` (previous).
+    fn find_job(&self, spec: Option<&str>) -> Result<usize, String> {
+        let n = self.jobs.len();
+        let found = match spec.unwrap_or("%+") {
+            "%" | "%+" | "%%" => n.checked_sub(1),
+            "%-" => n.checked_sub(2),
+            s => s.strip_prefix('%').and_then(|id| id.parse().ok())
+                .and_then(|id: usize| self.jobs.iter().position(|j| j.id == id)),
+        };
+        found.ok_or_else(|| match spec {
+            None => "no current job".to_string(),
+            Some(s) => format!("{}: no such job", s),
+        })
+    }
+
+    // ── Built-ins ─────────────────────────────────────────────────
+
+    pub fn builtin_jobs(&mut self, out: &mut dyn Sink) -> i32 {
+        for i in 0..self.jobs.len() {
+            self.jobs[i].poll(false);
+            let _ = out.write_all(self.job_line(i).as_bytes());
+        }
+        self.jobs.retain(|j| !matches!(j.state, JobState::Done(_)));
+        0
+    }
+
+    /// `fg [%n]`: continue a job with the terminal and wait for it.
+    pub fn builtin_fg(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        let idx = match self.find_job(args.first().map(String::as_str)) {
+            Ok(i) => i,
+            Err(msg) => { self.report(out, &format!("fg: {}", msg)); return 1; }
+        };
+        let mut job = self.jobs.remove(idx);
+        let _ = out.write_all(format!("{}\n", job.cmd).as_bytes());
+        let _ = out.flush();
+        platform::give_terminal(Some(job.pgid()));
+        if job.state == JobState::Stopped && let Some(cont) = platform::signal_number("CONT") {
+            let _ = job.signal(cont);
+        }
+        job.poll(true);
+        platform::give_terminal(None);
+        match job.state {
+            JobState::Done(code) => code,
+            _ => {
+                self.jobs.push(job);
+                let line = self.job_line(self.jobs.len() - 1);
+                let _ = out.write_all(format!("\n{}", line).as_bytes());
+                stopped_status()
+            }
+        }
+    }
+
+    /// `bg [%n]`: let a stopped job carry on in the background.
+    pub fn builtin_bg(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        let idx = match self.find_job(args.first().map(String::as_str)) {
+            Ok(i) => i,
+            Err(msg) => { self.report(out, &format!("bg: {}", msg)); return 1; }
+        };
+        let job = &mut self.jobs[idx];
+        if job.state != JobState::Stopped {
+            let msg = format!("bg: job {} already in background", job.id);
+            self.report(out, &msg);
+            return 0;
+        }
+        if let Some(cont) = platform::signal_number("CONT") { let _ = job.signal(cont); }
+        let _ = out.write_all(format!("[{}] {} &\n", job.id, job.cmd).as_bytes());
+        0
+    }
+
+    /// `wait [%n | pid]...`: wait for the given jobs, or for all running
+    /// ones, and report the jobs that have finished. The status is that of the last job waited for, or 130 when
+    /// Ctrl-C cuts the wait short.
+    pub fn builtin_wait(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        let mut status = 0;
+        if args.is_empty() {
+            for job in &mut self.jobs { job.wait_interruptibly(); }
+        }
+        for spec in args {
+            let found = if spec.starts_with('%') {
+                self.find_job(Some(spec))
+            } else {
+                let pid = spec.parse::<u32>().ok();
+                self.jobs.iter().position(|j| j.procs.iter().any(|c| Some(c.id()) == pid))
+                    .ok_or_else(|| format!("{}: not a child of this shell", spec))
+            };
+            let idx = match found {
+                Ok(i) => i,
+                Err(msg) => { self.report(out, &format!("wait: {}", msg)); status = 127; continue; }
+            };
+            let job = &mut self.jobs[idx];
+            job.wait_interruptibly();
+            status = match job.state {
+                JobState::Done(code) => code,
+                JobState::Stopped => stopped_status(),
+                JobState::Running => 130,
+            };
+        }
+        // Say which jobs ended, as the prompt would have, before they go.
+        for i in 0..self.jobs.len() {
+            if matches!(self.jobs[i].state, JobState::Done(_)) { let _ = out.write_all(self.job_line(i).as_bytes()); }
+        }
+        self.jobs.retain(|j| !matches!(j.state, JobState::Done(_)));
+        if platform::interrupt_pending() { 130 } else { status }
+    }
+
+    /// `kill [-SIG | -s SIG | -l] %n | pid...`
+    pub fn builtin_kill(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        if args.first().is_some_and(|a| a == "-l") {
+            for (name, n) in platform::SIGNALS { let _ = out.write_all(format!("{:>2}) {}\n", n, name).as_bytes()); }
+            return 0;
+        }
+        let (spec, targets) = match args.first().map(String::as_str) {
+            Some("-s") if args.len() > 1 => (args[1].as_str(), &args[2..]),
+            Some(a) if a.len() > 1 && a.starts_with('-') => (&a[1..], &args[1..]),
+            _ => ("TERM", args),
+        };
+        let Some(sig) = platform::signal_number(spec) else {
+            self.report(out, &format!("kill: {}: invalid signal specification", spec));
+            return 1;
+        };
+        if targets.is_empty() {
+            let _ = out.write_all(b"Usage: kill [-SIG] %job|pid...\n");
+            return 2;
+        }
+        let mut status = 0;
+        for t in targets {
+            let result = if t.starts_with('%') {
+                match self.find_job(Some(t)) {
+                    Ok(i) => self.jobs[i].signal(sig),
+                    Err(msg) => Err(io::Error::other(msg)),
+                }
+            } else {
+                match t.parse::<u32>() {
+                    Ok(pid) => platform::send_signal(pid, sig, false),
+                    Err(_) => Err(io::Error::other("arguments must be process or job IDs")),
+                }
+            };
+            if let Err(e) = result {
+                let msg = os_reason(&e);
+                let msg = if msg.starts_with(t.as_str()) { msg } else { format!("{}: {}", t, msg) };
+                self.report(out, &format!("kill: {}", msg));
+                status = 1;
+            }
+        }
+        status
+    }
+}
@@ -0,0 +1,334 @@
+// ══════════════════════════════════════════════════════════════════
+// ls / ll — directory listings
+// ══════════════════════════════════════════════════════════════════
+
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::pipeline::Sink;
+use crate::{local_datetime, os_reason, platform, Shell};
+
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Sort {
+    /// Directories first, then by name.
+    #[default]
+    Name,
+    /// Newest first.
+    Time,
+    /// Largest first.
+    Size,
+}
+
+#[derive(Default)]
+struct Options {
+    all: bool,
+    long: bool,
+    human: bool,
+    sort: Sort,
+    reverse: bool,
+    recursive: bool,
+    one_per_line: bool,
+    json: bool,
+}
+
+/// Split `ls` arguments into options and paths. `-lah` combines
+/// letters, and `--` ends the options. Returns the unknown option on error.
+fn parse_options(args: &[String]) -> Result<(Options, Vec<String>), String> {
+    let mut opts = Options::default();
+    let mut paths = Vec::new();
+    let mut only_paths = false;
+    for arg in args {
+        if only_paths || !arg.starts_with('-') || arg == "-" { paths.push(arg.clone()); continue; }
+        match arg.as_str() {
+            "--" => only_paths = true,
+            "--json" => opts.json = true,
+            "--all" => opts.all = true,
+            "--reverse" => opts.reverse = true,
+            "--recursive" => opts.recursive = true,
+            _ if arg.starts_with("--") => return Err(arg.clone()),
+            _ => for c in arg[1..].chars() {
+                match c {
+                    'a' => opts.all = true,
+                    'l' => opts.long = true,
+                    'h' => opts.human = true,
+                    't' => opts.sort = Sort::Time,
+                    'S' => opts.sort = Sort::Size,
+                    'r' => opts.reverse = true,
+                    'R' => opts.recursive = true,
+                    '1' => opts.one_per_line = true,
+                    _ => return Err(format!("-{}", c)),
+                }
+            },
+        }
+    }
+    Ok((opts, paths))
+}
+
+/// One file, directory or symlink being listed.
+struct Entry {
+    name: String,
+    path: PathBuf,
+    /// Metadata of the entry itself, not of what a symlink points to.
+    meta: Metadata,
+    /// A directory, or a symlink to one.
+    is_dir: bool,
+    target: Option<PathBuf>,
+}
+
+impl Entry {
+    fn new(name: String, path: PathBuf) -> io::Result<Entry> {
+        let meta = fs::symlink_metadata(&path)?;
+        let target = if meta.is_symlink() { fs::read_link(&path).ok() } else { None };
+        let is_dir = path.is_dir();
+        Ok(Entry { name, path, meta, is_dir, target })
+    }
+
+    fn mtime(&self) -> u64 {
+        self.meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs())
+    }
+
+    fn kind(&self) -> &'static str {
+        if self.target.is_some() { "symlink" } else if self.is_dir { "dir" } else { "file" }
+    }
+
+    /// The name as listed on a terminal, and its width on screen.
+    fn display(&self, tty: bool) -> (String, usize) {
+        let width = self.name.chars().count() + self.is_dir as usize;
+        let text = match (tty, self.target.is_some(), self.is_dir) {
+            (false, _, true) => format!("{}/", self.name),
+            (false, _, false) => self.name.clone(),
+            (true, true, dir) => format!("\x1b[36m{}{}\x1b[0m", self.name, if dir { "/" } else { "" }),
+            (true, false, true) => format!("\x1b[34m{}/\x1b[0m", self.name),
+            (true, false, false) => self.name.clone(),
+        };
+        (text, width)
+    }
+}
+
+fn sort_entries(entries: &mut [Entry], opts: &Options) {
+    match opts.sort {
+        Sort::Name => entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name))),
+        Sort::Time => entries.sort_by(|a, b| b.mtime().cmp(&a.mtime()).then_with(|| a.name.cmp(&b.name))),
+        Sort::Size => entries.sort_by(|a, b| b.meta.len().cmp(&a.meta.len()).then_with(|| a.name.cmp(&b.name))),
+    }
+    if opts.reverse { entries.reverse(); }
+}
+
+/// `YYYY-MM-DD HH:MM`.
+fn format_time(secs: u64) -> String {
+    let (y, mo, d, h, mi, _) = local_datetime(secs);
+    format!("{}-{:02}-{:02} {:02}:{:02}", y, mo, d, h, mi)
+}
+
+fn json_string(s: &str) -> String {
+    let mut j = String::with_capacity(s.len() + 2);
+    j.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => j.push_str("\\\""),
+            '\\' => j.push_str("\\\\"),
+            '\n' => j.push_str("\\n"),
+            '\t' => j.push_str("\\t"),
+            c if (c as u32) < 0x20 => j.push_str(&format!("\\u{:04x}", c as u32)),
+            c => j.push(c),
+        }
+    }
+    j.push('"');
+    j
+}
+
+fn json_entry(e: &Entry) -> String {
+    let target = e.target.as_ref()
+        .map(|t| format!(", \"target\": {}", json_string(&t.to_string_lossy())))
+        .unwrap_or_default();
+    format!(
+        "{{\"name\": {}, \"path\": {}, \"type\": \"{}\", \"size\": {}, \"modified\": {}, \"mode\": \"{}\", \"readonly\": {}{}}}",
+        json_string(&e.name), json_string(&e.path.to_string_lossy()), e.kind(), e.meta.len(), e.mtime(),
+        platform::mode_string(&e.meta), e.meta.permissions().readonly(), target,
+    )
+}
+
+impl Shell {
+    /// `ls [-alhtSrR1] [--json] [path...]`; `ll` is `ls -l`.
+    pub fn builtin_ls(&mut self, cmd: &str, args: &[String], out: &mut dyn Sink) -> i32 {
+        let (mut opts, mut paths) = match parse_options(args) {
+            Ok(parsed) => parsed,
+            Err(bad) => {
+                self.report(out, &format!("{}: {}: unknown option", cmd, bad));
+                let _ = write!(out, "Usage: {} [-alhtSrR1] [--json] [path...]\n", cmd);
+                return 2;
+            }
+        };
+        opts.long |= cmd == "ll";
+        if paths.is_empty() { paths.push(".".into()); }
+
+        let mut status = 0;
+        let (mut files, mut dirs) = (Vec::new(), Vec::new());
+        for p in &paths {
+            match Entry::new(p.clone(), PathBuf::from(p)) {
+                Ok(e) if e.is_dir => dirs.push(e),
+                Ok(e) => files.push(e),
+                Err(e) => { self.report(out, &format!("{}: {}: {}", cmd, p, os_reason(&e))); status = 1; }
+            }
+        }
+        sort_entries(&mut files, &opts);
+        sort_entries(&mut dirs, &opts);
+
+        if opts.json {
+            let mut all = files;
+            for d in &dirs {
+                if !self.collect_json(cmd, &d.path, &opts, &mut all, out) { status = 1; }
+            }
+            let items: Vec<String> = all.iter().map(|e| format!("  {}", json_entry(e))).collect();
+            if items.is_empty() { let _ = out.write_all(b"[]\n"); }
+            else { let _ = write!(out, "[\n{}\n]\n", items.join(",\n")); }
+            return status;
+        }
+
+        self.print_entries(&files, &opts, out);
+        let headers = paths.len() > 1 || opts.recursive;
+        for (i, d) in dirs.iter().enumerate() {
+            if i > 0 || !files.is_empty() { let _ = out.write_all(b"\n"); }
+            if !self.list_dir(cmd, &d.path, headers, &opts, out) { status = 1; }
+        }
+        status
+    }
+
+    /// Entries of `dir`, sorted, without hidden ones unless `-a`.
+    fn read_entries(&mut self, cmd: &str, dir: &Path, opts: &Options, out: &mut dyn Sink) -> Option<Vec<Entry>> {
+        let read = match fs::read_dir(dir) {
+            Ok(read) => read,
+            Err(e) => { self.report(out, &format!("{}: {}: {}", cmd, dir.display(), os_reason(&e))); return None; }
+        };
+        let mut entries: Vec<Entry> = read.flatten()
+            .filter_map(|e| Entry::new(e.file_name().to_string_lossy().into_owned(), e.path()).ok())
+            .filter(|e| opts.all || !platform::is_hidden(&e.name, &e.meta))
+            .collect();
+        sort_entries(&mut entries, opts);
+        Some(entries)
+    }
+
+    /// List one directory, with a `dir:` header when several are shown,
+    /// then its subdirectories for `-R` (not following symlinks).
+    fn list_dir(&mut self, cmd: &str, dir: &Path, header: bool, opts: &Options, out: &mut dyn Sink) -> bool {
+        let Some(entries) = self.read_entries(cmd, dir, opts, out) else { return false };
+        if header { let _ = write!(out, "{}:\n", dir.display()); }
+        self.print_entries(&entries, opts, out);
+        let mut ok = true;
+        if opts.recursive {
+            for e in entries.iter().filter(|e| e.is_dir && e.target.is_none()) {
+                let _ = out.write_all(b"\n");
+                ok &= self.list_dir(cmd, &e.path, true, opts, out);
+            }
+        }
+        ok
+    }
+
+    /// Add the entries of `dir` (and with `-R` everything below it) to `all`.
+    fn collect_json(&mut self, cmd: &str, dir: &Path, opts: &Options, all: &mut Vec<Entry>, out: &mut dyn Sink) -> bool {
+        let Some(entries) = self.read_entries(cmd, dir, opts, out) else { return false };
+        let mut ok = true;
+        for e in entries {
+            let sub = (opts.recursive && e.is_dir && e.target.is_none()).then(|| e.path.clone());
+            all.push(e);
+            if let Some(sub) = sub { ok &= self.collect_json(cmd, &sub, opts, all, out); }
+        }
+        ok
+    }
+
+    fn print_entries(&self, entries: &[Entry], opts: &Options, out: &mut dyn Sink) {
+        if entries.is_empty() { return; }
+        let tty = out.is_tty();
+        if opts.long {
+            let sizes: Vec<String> = entries.iter().map(|e| {
+                if e.is_dir && e.target.is_none() { "<DIR>".into() }
+                else if opts.human { Self::human_size(e.meta.len()) }
+                else { e.meta.len().to_string() }
+            }).collect();
+            let width = sizes.iter().map(String::len).max().unwrap_or(0);
+            for (e, size) in entries.iter().zip(&sizes) {
+                let target = e.target.as_ref().map(|t| format!(" -> {}", t.display())).unwrap_or_default();
+                let _ = write!(out, "{}  {:>w$}  {}  {}{}\n",
+                    platform::mode_string(&e.meta), size, format_time(e.mtime()), e.display(tty).0, target, w = width);
+            }
+            return;
+        }
+        if !tty || opts.one_per_line {
+            for e in entries {
+                let name = if tty { e.display(true).0 } else { e.name.clone() };
+                let _ = write!(out, "{}\n", name);
+            }
+            return;
+        }
+        // Columns filled top to bottom, as many as fit the terminal.
+        let names: Vec<(String, usize)> = entries.iter().map(|e| e.display(true)).collect();
+        let col_width = names.iter().map(|n| n.1).max().unwrap_or(0) + 2;
+        let cols = (platform::terminal_width() / col_width).max(1);
+        let rows = names.len().div_ceil(cols);
+        for r in 0..rows {
+            let mut line = String::new();
+            for c in 0..cols {
+                let Some((text, w)) = names.get(c * rows + r) else { break };
+                line.push_str(text);
+                if (c + 1) * rows + r < names.len() { line.push_str(&" ".repeat(col_width - w)); }
+            }
+            let _ = write!(out, "{}\n", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> { list.iter().map(|a| a.to_string()).collect() }
+
+    #[test]
+    fn options() {
+        let (opts, paths) = parse_options(&args(&["-lah", "src", "--", "-t", "-"])).unwrap();
+        assert!(opts.long && opts.all && opts.human && !opts.reverse);
+        assert!(opts.sort == Sort::Name);
+        assert_eq!(paths, ["src", "-t", "-"]);
+        let (opts, paths) = parse_options(&args(&["-tr", "-S1R", "--json", "--all"])).unwrap();
+        assert!(opts.sort == Sort::Size && opts.reverse && opts.one_per_line && opts.recursive && opts.json && opts.all);
+        assert!(paths.is_empty());
+        assert_eq!(parse_options(&args(&["-lx"])).err().as_deref(), Some("-x"));
+        assert_eq!(parse_options(&args(&["--long"])).err().as_deref(), Some("--long"));
+    }
+
+    #[test]
+    fn sorting_and_json() {
+        let root = env::temp_dir().join(format!("nes-ls-{}", std::process::id()));
+        fs::create_dir_all(root.join("zdir")).unwrap();
+        fs::write(root.join("big"), "0123456789".repeat(10_000)).unwrap();
+        fs::write(root.join("a \"q\""), "1").unwrap();
+        let mut entries: Vec<Entry> = ["big", "zdir", "a \"q\""].iter()
+            .map(|n| Entry::new(n.to_string(), root.join(n)).unwrap())
+            .collect();
+        let names = |entries: &[Entry]| entries.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+        let mut opts = Options::default();
+        sort_entries(&mut entries, &opts);
+        assert_eq!(names(&entries), ["zdir", "a \"q\"", "big"]);
+        opts.reverse = true;
+        sort_entries(&mut entries, &opts);
+        assert_eq!(names(&entries), ["big", "a \"q\"", "zdir"]);
+        opts = Options { sort: Sort::Size, ..Options::default() };
+        sort_entries(&mut entries, &opts);
+        assert_eq!(entries[0].name, "big");
+
+        let json = json_entry(&entries[0]);
+        assert!(json.starts_with("{\"name\": \"big\", \"path\": "), "{}", json);
+        assert!(json.contains("\"type\": \"file\", \"size\": 100000, \"modified\": "), "{}", json);
+        assert!(json.ends_with("\"readonly\": false}"), "{}", json);
+        let quoted = entries.iter().find(|e| e.name.starts_with('a')).unwrap();
+        assert!(json_entry(quoted).starts_with("{\"name\": \"a \\\"q\\\"\""));
+        let dir = entries.iter().find(|e| e.is_dir).unwrap();
+        assert!(json_entry(dir).contains("\"type\": \"dir\""));
+        assert_eq!(json_string("tab\there\u{1}\\"), "\"tab\\there\\u0001\\\\\"");
+        let _ = fs::remove_dir_all(&root);
+    }
+}
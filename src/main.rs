@@ -1,73 +1,305 @@
+#![allow(clippy::write_with_newline)]
+
 use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::io::{self, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
+
+mod complete;
+mod cond;
+mod dirs;
+mod editor;
+mod expand;
+mod history;
+mod jobs;
+mod ls;
+mod pipeline;
+mod platform;
+mod prompt;
+mod regex;
+mod signals;
+mod syntax;
+
+use pipeline::{ErrOut, Input, Sink};
+use syntax::{Connector, Pipeline};
+
+/// Every command handled by `Shell::dispatch` itself.
+const BUILTINS: &[&str] = &[
+    "exit", "quit", "help", "cd", "let", "echo", "read", "sleep", "exists", "count", "typeof",
+    "set", "unset", "export", "history", "pwd", "ls", "ll", "cat", "head", "tail", "wc",
+    "touch", "mkdir", "rm", "cp", "mv", "grep", "find", "tree", "whoami", "hostname", "os",
+    "env", "time", "date", "calc", "open", "clear", "cls", "run", "source", "which", "alias", "size", "hex",
+    "break", "continue", "local", "return", "shift", "jobs", "fg", "bg", "wait", "kill", "trap",
+    "pushd", "popd", "dirs", "z",
+];
+
+/// Lines that open a block closed by `end`.
+fn opens_block(line: &str) -> bool {
+    ["if ", "for ", "while ", "until ", "fn ", "def ", "match "].iter().any(|k| line.starts_with(k))
+}
+
+/// Blocks opened minus blocks closed in `text`; input is complete once
+/// this is no longer positive.
+fn block_balance(text: &str) -> i32 {
+    text.lines().map(str::trim).map(|l| if opens_block(l) { 1 } else if l == "end" { -1 } else { 0 }).sum()
+}
+
+fn is_if_branch(line: &str) -> bool {
+    line == "else" || line.starts_with("elif ") || line.starts_with("else if ")
+}
+
+fn is_case_branch(line: &str) -> bool {
+    line == "default" || line.starts_with("case ")
+}
+
+/// Pending jump out of the current loop body.
+#[derive(Clone, Copy, PartialEq)]
+enum Flow {
+    Normal,
+    /// `break n`: leave this many enclosing loops.
+    Break(u32),
+    /// `continue n`: leave `n - 1` loops and start the next pass of the last.
+    Continue(u32),
+    /// `return`: leave the current function.
+    Return,
+}
+
+/// Deepest allowed chain of function calls, well inside the main thread's stack.
+const MAX_CALL_DEPTH: usize = 200;
+
+/// A function defined with `fn name` / `def name` … `end`.
+#[derive(Clone)]
+struct Function {
+    body: Arc<[String]>,
+    /// Script line of the `fn` header, so body lines report real positions.
+    line: usize,
+}
 
 struct Shell {
     vars: HashMap<String, String>,
-    history: Vec<String>,
+    history: history::History,
     running: bool,
+    /// Exit status of the last command, exposed as `$?`.
+    status: i32,
+    /// How long the last interactive command took, for `\d` in `PROMPT`.
+    last_duration: Duration,
+    /// `set -e`: stop at the first command that fails.
+    errexit: bool,
+    /// Current stderr target, changed for the duration of `2>` and friends.
+    err: ErrOut,
+    /// Line of the script being executed (1-based), for error positions.
+    lineno: usize,
+    /// Offset of the block currently executing within its script.
+    line_base: usize,
+    /// Set by `break` / `continue` until the loop they target handles it.
+    flow: Flow,
+    /// Number of loops currently running, to validate `break n`.
+    loop_depth: u32,
+    functions: HashMap<String, Function>,
+    /// Positional parameters `$1..$n` of the current script or function call.
+    args: Vec<String>,
+    /// `$0`: the running script, or `nes` when interactive.
+    script: String,
+    /// Set for `nes enter-full`: job numbers and notifications are shown.
+    interactive: bool,
+    /// `pushd` stack, most recent first; the current directory is not on it.
+    dir_stack: Vec<std::path::PathBuf>,
+    /// Background jobs, oldest first; the last one is the current job.
+    jobs: Vec<jobs::BgJob>,
+    /// `$!`: process ID of the last background job.
+    last_bg: Option<u32>,
+    /// Set by an untrapped Ctrl-C or a failed `${name:?}`: everything
+    /// running is abandoned until the next interactive command.
+    interrupted: bool,
+    /// `trap` handlers by condition (`INT`, `TERM`, `EXIT`); an empty
+    /// handler ignores the signal.
+    traps: HashMap<String, String>,
+    /// Variables shadowed by `local` in each active call, with the value
+    /// to restore when it returns (`None` if the variable did not exist).
+    locals: Vec<HashMap<String, Option<String>>>,
+    /// Standard input of the current function call, shared by the
+    /// commands in its body.
+    input: Input,
+    /// Set in the copy of the shell running a built-in pipeline stage;
+    /// raised once the next stage stops reading.
+    pipe_closed: Option<Arc<AtomicBool>>,
 }
 
 impl Shell {
     fn new() -> Self {
         Self {
             vars: HashMap::new(),
-            history: Vec::with_capacity(512),
+            history: history::History::default(),
             running: true,
+            status: 0,
+            last_duration: Duration::ZERO,
+            errexit: false,
+            err: ErrOut::Terminal,
+            lineno: 1,
+            line_base: 0,
+            flow: Flow::Normal,
+            loop_depth: 0,
+            functions: HashMap::new(),
+            args: Vec::new(),
+            script: "nes".into(),
+            interactive: false,
+            dir_stack: Vec::new(),
+            jobs: Vec::new(),
+            last_bg: None,
+            interrupted: false,
+            traps: HashMap::new(),
+            locals: Vec::new(),
+            input: Input::Inherit,
+            pipe_closed: None,
         }
     }
 
-    fn prompt(&self, out: &mut impl Write) {
-        let cwd = env::current_dir()
-            .map(|p| p.to_string_lossy().into_owned())
-            .unwrap_or_else(|_| "?".into());
-        let _ = write!(out, "\x1b[36m{}\x1b[0m \x1b[33mnes>\x1b[0m ", cwd);
-        let _ = out.flush();
-    }
-
     fn block_prompt(out: &mut impl Write) {
         let _ = write!(out, "\x1b[33m ...>\x1b[0m ");
         let _ = out.flush();
     }
 
-    // ── Block execution (if/for/comments) ─────────────────────────
+    // ── Block execution (if/for/while/match/fn/comments) ──────────
 
-    fn exec_lines(&mut self, lines: &[String], out: &mut BufWriter<io::StdoutLock<'_>>) {
+    fn exec_lines(&mut self, lines: &[String], out: &mut dyn Sink) {
         let mut pc = 0;
-        while pc < lines.len() && self.running {
+        while pc < lines.len() && self.flow == Flow::Normal && self.check_signals(out) {
             let raw = lines[pc].trim();
             if raw.is_empty() || raw.starts_with('#') { pc += 1; continue; }
+            self.lineno = self.line_base + pc + 1;
 
-            if raw.starts_with("if ") {
-                let (else_idx, end_idx) = Self::find_block_end(lines, pc);
+            if let Some(cond) = raw.strip_prefix("if ") {
+                let (branches, end_idx) = Self::find_block_end(lines, pc, is_if_branch);
                 if end_idx >= lines.len() {
-                    let _ = write!(out, "\x1b[31mnes: missing 'end' for 'if'\x1b[0m\n");
+                    self.report(out, "missing 'end' for 'if'");
+                    self.status = 2;
                     return;
                 }
-                let cond = self.expand_vars(&raw[3..]);
-                if self.eval_condition(&cond) {
-                    let stop = else_idx.unwrap_or(end_idx);
-                    let body: Vec<String> = lines[pc + 1..stop].to_vec();
-                    self.exec_lines(&body, out);
-                } else if let Some(ei) = else_idx {
-                    let body: Vec<String> = lines[ei + 1..end_idx].to_vec();
-                    self.exec_lines(&body, out);
+                if let Some(pos) = branches.iter().position(|&b| lines[b].trim() == "else")
+                    && let Some(&after) = branches.get(pos + 1)
+                {
+                    self.lineno = self.line_base + after + 1;
+                    self.report(out, "'else' must be the last branch of 'if'");
+                    self.status = 2;
+                    return;
+                }
+                // Try each branch in turn: the `if` line, then every
+                // `elif` / `else if`, then `else` (no condition).
+                let mut start = pc;
+                let mut cond = Some(cond);
+                for next in branches.iter().copied().chain([end_idx]) {
+                    let taken = match cond {
+                        None => true,
+                        Some(c) => match self.eval_condition(c, out) {
+                            Ok(b) => b,
+                            Err(msg) => { self.report(out, &msg); self.status = 2; break; }
+                        },
+                    };
+                    if taken {
+                        self.exec_body(lines, start + 1, next, out);
+                        break;
+                    }
+                    if next == end_idx { break; }
+                    start = next;
+                    self.lineno = self.line_base + next + 1;
+                    let line = lines[next].trim();
+                    cond = line.strip_prefix("elif ").or_else(|| line.strip_prefix("else if "));
                 }
                 pc = end_idx + 1;
             } else if raw.starts_with("for ") {
-                let (_, end_idx) = Self::find_block_end(lines, pc);
+                let (_, end_idx) = Self::find_block_end(lines, pc, |_| false);
                 if end_idx >= lines.len() {
-                    let _ = write!(out, "\x1b[31mnes: missing 'end' for 'for'\x1b[0m\n");
+                    self.report(out, "missing 'end' for 'for'");
+                    self.status = 2;
                     return;
                 }
-                let body: Vec<String> = lines[pc + 1..end_idx].to_vec();
-                let header = self.expand_vars(raw);
-                self.exec_for(&header, &body, out);
+                let saved = self.line_base;
+                self.line_base += pc + 1;
+                let res = self.exec_for(raw, &lines[pc + 1..end_idx], out);
+                self.line_base = saved;
+                if let Err(msg) = res {
+                    self.report(out, &msg);
+                    self.status = 1;
+                }
+                pc = end_idx + 1;
+            } else if let Some(subject) = raw.strip_prefix("match ") {
+                let (arms, end_idx) = Self::find_block_end(lines, pc, is_case_branch);
+                if end_idx >= lines.len() {
+                    self.report(out, "missing 'end' for 'match'");
+                    self.status = 2;
+                    return;
+                }
+                let first_arm = arms.first().copied().unwrap_or(end_idx);
+                if let Some(stray) = (pc + 1..first_arm).find(|&i| !lines[i].trim().is_empty() && !lines[i].trim().starts_with('#')) {
+                    self.lineno = self.line_base + stray + 1;
+                    self.report(out, "expected 'case' inside 'match'");
+                    self.status = 2;
+                    return;
+                }
+                let value = match syntax::parse_words(subject, self.lineno).map_err(|e| e.to_string())
+                    .and_then(|words| self.expand_words(&words))
+                {
+                    Ok(v) => v.join(" "),
+                    Err(msg) => { self.report(out, &msg); self.status = 1; pc = end_idx + 1; continue; }
+                };
+                self.status = 0;
+                for (k, &arm) in arms.iter().enumerate() {
+                    self.lineno = self.line_base + arm + 1;
+                    let taken = match lines[arm].trim().strip_prefix("case ") {
+                        None => true,
+                        Some(patterns) => match self.case_matches(patterns, &value) {
+                            Ok(m) => m,
+                            Err(msg) => { self.report(out, &msg); self.status = 1; break; }
+                        },
+                    };
+                    if taken {
+                        let stop = arms.get(k + 1).copied().unwrap_or(end_idx);
+                        self.exec_body(lines, arm + 1, stop, out);
+                        break;
+                    }
+                }
+                pc = end_idx + 1;
+            } else if let Some(name) = raw.strip_prefix("fn ").or_else(|| raw.strip_prefix("def ")) {
+                let (_, end_idx) = Self::find_block_end(lines, pc, |_| false);
+                if end_idx >= lines.len() {
+                    self.report(out, &format!("missing 'end' for '{}'", &raw[..raw.find(' ').unwrap_or(0)]));
+                    self.status = 2;
+                    return;
+                }
+                let name = name.trim().trim_end_matches("()");
+                if name.is_empty() || name.contains(char::is_whitespace) || name.contains(['$', '"', '\'', '|', '&', ';', '<', '>']) {
+                    self.report(out, &format!("{}: invalid function name", name));
+                    self.status = 2;
+                } else {
+                    let body: Arc<[String]> = lines[pc + 1..end_idx].into();
+                    self.functions.insert(name.to_string(), Function { body, line: self.line_base + pc + 1 });
+                    self.status = 0;
+                }
+                pc = end_idx + 1;
+            } else if raw.starts_with("while ") || raw.starts_with("until ") {
+                let (keyword, cond) = raw.split_at(5);
+                let (_, end_idx) = Self::find_block_end(lines, pc, |_| false);
+                if end_idx >= lines.len() {
+                    self.report(out, &format!("missing 'end' for '{}'", keyword));
+                    self.status = 2;
+                    return;
+                }
+                self.loop_depth += 1;
+                while self.check_signals(out) {
+                    match self.eval_condition(cond, out) {
+                        Ok(b) if b == (keyword == "until") => break,
+                        Ok(_) => {}
+                        Err(msg) => { self.report(out, &msg); self.status = 2; break; }
+                    }
+                    self.exec_body(lines, pc + 1, end_idx, out);
+                    if self.end_of_pass() { break; }
+                }
+                self.loop_depth -= 1;
                 pc = end_idx + 1;
             } else {
                 self.exec(raw, out);
@@ -76,67 +308,62 @@ impl Shell {
         }
     }
 
-    fn find_block_end(lines: &[String], start: usize) -> (Option<usize>, usize) {
+    /// Run `lines[start..stop]` as a nested block, keeping line numbers
+    /// relative to the enclosing script.
+    fn exec_body(&mut self, lines: &[String], start: usize, stop: usize, out: &mut dyn Sink) {
+        let saved = self.line_base;
+        self.line_base += start;
+        self.exec_lines(&lines[start..stop], out);
+        self.line_base = saved;
+    }
+
+    /// Find the `end` closing the block opened at `start`, plus the lines
+    /// of its own branches (`elif`/`else` or `case`) picked by `is_branch`.
+    fn find_block_end(lines: &[String], start: usize, is_branch: fn(&str) -> bool) -> (Vec<usize>, usize) {
         let mut depth = 0u32;
-        let mut else_pos = None;
-        for i in (start + 1)..lines.len() {
-            let l = lines[i].trim();
-            if l.starts_with("if ") || l.starts_with("for ") { depth += 1; }
+        let mut branches = Vec::new();
+        for (i, line) in lines.iter().enumerate().skip(start + 1) {
+            let l = line.trim();
+            if opens_block(l) { depth += 1; }
             else if l == "end" {
-                if depth == 0 { return (else_pos, i); }
+                if depth == 0 { return (branches, i); }
                 depth -= 1;
-            } else if l == "else" && depth == 0 {
-                else_pos = Some(i);
+            } else if depth == 0 && is_branch(l) {
+                branches.push(i);
             }
         }
-        (None, lines.len()) // no matching end
+        (Vec::new(), lines.len()) // no matching end
     }
 
-    fn eval_condition(&self, cond: &str) -> bool {
-        let cond = cond.trim();
-        if let Some(rest) = cond.strip_prefix("exists ") {
-            return Path::new(rest.trim()).exists();
-        }
-        if let Some(rest) = cond.strip_prefix("not ") {
-            return !self.eval_condition(rest);
-        }
-        if let Some(pos) = cond.find(" >= ") {
-            let l: f64 = cond[..pos].trim().parse().unwrap_or(f64::NAN);
-            let r: f64 = cond[pos + 4..].trim().parse().unwrap_or(f64::NAN);
-            return l >= r;
-        }
-        if let Some(pos) = cond.find(" <= ") {
-            let l: f64 = cond[..pos].trim().parse().unwrap_or(f64::NAN);
-            let r: f64 = cond[pos + 4..].trim().parse().unwrap_or(f64::NAN);
-            return l <= r;
-        }
-        if let Some(pos) = cond.find(" == ") {
-            return cond[..pos].trim() == cond[pos + 4..].trim();
-        }
-        if let Some(pos) = cond.find(" != ") {
-            return cond[..pos].trim() != cond[pos + 4..].trim();
-        }
-        if let Some(pos) = cond.find(" > ") {
-            let l: f64 = cond[..pos].trim().parse().unwrap_or(f64::NAN);
-            let r: f64 = cond[pos + 3..].trim().parse().unwrap_or(f64::NAN);
-            return l > r;
-        }
-        if let Some(pos) = cond.find(" < ") {
-            let l: f64 = cond[..pos].trim().parse().unwrap_or(f64::NAN);
-            let r: f64 = cond[pos + 3..].trim().parse().unwrap_or(f64::NAN);
-            return l < r;
+    /// Whether `value` matches one of the `|`-separated patterns of a
+    /// `case` line. Quoted patterns match literally.
+    fn case_matches(&mut self, patterns: &str, value: &str) -> Result<bool, String> {
+        let value: Vec<char> = value.chars().collect();
+        for alt in patterns.split('|').map(str::trim) {
+            let quoted = alt.len() >= 2 && (alt.starts_with('"') && alt.ends_with('"') || alt.starts_with('\'') && alt.ends_with('\''));
+            let hit = if quoted {
+                let inner = &alt[1..alt.len() - 1];
+                let inner = if alt.starts_with('"') { self.expand_vars(inner)? } else { inner.to_string() };
+                inner.chars().eq(value.iter().copied())
+            } else {
+                let pat: Vec<char> = self.expand_vars(alt)?.chars().collect();
+                expand::wildcard_match(&pat, &value)
+            };
+            if hit { return Ok(true); }
         }
-        !cond.is_empty() && cond != "false" && cond != "0"
+        Ok(false)
     }
 
-    fn exec_for(&mut self, header: &str, body: &[String], out: &mut BufWriter<io::StdoutLock<'_>>) {
+    fn exec_for(&mut self, header: &str, body: &[String], out: &mut dyn Sink) -> Result<(), String> {
         let after = header[4..].trim();
         let (var, rest) = match after.find(" in ") {
             Some(p) => (after[..p].trim(), after[p + 4..].trim()),
-            None => return,
+            None => return Ok(()),
         };
         let var = var.to_string();
         let items: Vec<String> = if rest == "files" || rest.starts_with("files ") {
+            let rest = self.expand_vars(rest)?;
+            let rest = rest.as_str();
             let dir = if rest == "files" { "." } else { rest[5..].trim() };
             let dir = if dir.is_empty() { "." } else { dir };
             let mut v: Vec<String> = fs::read_dir(dir).ok()
@@ -144,175 +371,213 @@ impl Shell {
                 .unwrap_or_default();
             v.sort_unstable();
             v
-        } else if rest.starts_with("range ") {
-            let p: Vec<&str> = rest[6..].split_whitespace().collect();
+        } else if let Some(range) = rest.strip_prefix("range ") {
+            let range = self.expand_vars(range)?;
+            let p: Vec<&str> = range.split_whitespace().collect();
             if p.len() >= 2 {
                 let s: i64 = p[0].parse().unwrap_or(0);
                 let e: i64 = p[1].parse().unwrap_or(0);
                 if s <= e { (s..=e).map(|n| n.to_string()).collect() }
                 else { (e..=s).rev().map(|n| n.to_string()).collect() }
             } else { Vec::new() }
-        } else if rest.starts_with("lines ") {
-            let file = rest[6..].trim();
+        } else if let Some(file) = rest.strip_prefix("lines ") {
+            let file = self.expand_vars(file.trim())?;
             fs::read_to_string(file).ok()
                 .map(|c| c.lines().map(String::from).collect())
                 .unwrap_or_default()
         } else {
-            Self::split_args(rest)
+            let words = syntax::parse_words(rest, self.lineno).map_err(|e| e.to_string())?;
+            self.expand_words(&words)?
         };
+        self.loop_depth += 1;
         for item in items {
-            if !self.running { break; }
+            if !self.check_signals(out) { break; }
             self.vars.insert(var.clone(), item);
             self.exec_lines(body, out);
+            if self.end_of_pass() { break; }
+        }
+        self.loop_depth -= 1;
+        Ok(())
+    }
+
+    /// Called after each pass of a loop body: consume a `break` or
+    /// `continue` aimed at this loop and say whether the loop should stop.
+    fn end_of_pass(&mut self) -> bool {
+        match self.flow {
+            Flow::Normal => false,
+            Flow::Return => true,
+            Flow::Break(n) => {
+                self.flow = if n > 1 { Flow::Break(n - 1) } else { Flow::Normal };
+                true
+            }
+            Flow::Continue(n) => {
+                self.flow = if n > 1 { Flow::Continue(n - 1) } else { Flow::Normal };
+                n > 1
+            }
         }
     }
 
-    // ── Single-line execution (&&, |, >, >>) ─────────────────────
+    // ── Single-line execution (;, &&, ||, |, >, >>) ──────────────
 
-    fn exec(&mut self, raw: &str, out: &mut BufWriter<io::StdoutLock<'_>>) {
+    fn exec(&mut self, raw: &str, out: &mut dyn Sink) {
         let raw = raw.trim();
         if raw.is_empty() || raw.starts_with('#') { return; }
         if raw == "end" || raw == "else" { return; }
-        let raw = self.expand_vars(raw);
-        for chain in raw.split("&&") {
-            let chain = chain.trim();
-            if chain.is_empty() { continue; }
-            if chain.contains('|') {
-                self.exec_pipe(chain, out);
-            } else if let Some(pos) = chain.find(">>") {
-                let (cmd, file) = (&chain[..pos], chain[pos + 2..].trim());
-                let capture = self.capture(cmd.trim());
-                let _ = fs::OpenOptions::new().create(true).append(true).open(file)
-                    .and_then(|mut f| f.write_all(capture.as_bytes()));
-            } else if let Some(pos) = chain.find('>') {
-                let (cmd, file) = (&chain[..pos], chain[pos + 1..].trim());
-                let capture = self.capture(cmd.trim());
-                let _ = fs::write(file, capture);
-            } else {
-                self.dispatch(chain, out);
-                let _ = out.flush();
+        match syntax::parse(raw, self.lineno) {
+            Ok(list) => {
+                for (i, item) in list.items.iter().enumerate() {
+                    if self.flow != Flow::Normal || !self.check_signals(out) { break; }
+                    match item.connector {
+                        Connector::And if self.status != 0 => continue,
+                        Connector::Or if self.status == 0 => continue,
+                        _ => {}
+                    }
+                    self.status = if item.background {
+                        self.exec_background(&item.pipeline, out)
+                    } else {
+                        self.exec_pipeline(&item.pipeline, out)
+                    };
+                    // Like `set -e` elsewhere: a failure that is not tested by
+                    // a following `&&` / `||` stops the script.
+                    let tested = list.items.get(i + 1).is_some_and(|next| next.connector != Connector::Seq);
+                    if self.errexit && self.status != 0 && !tested {
+                        self.running = false;
+                    }
+                }
+            }
+            Err(e) => {
+                self.report(out, &e.to_string());
+                self.status = 2;
+            }
+        }
+    }
+
+    /// Run one pipeline and return its exit status (that of the last stage).
+    fn exec_pipeline(&mut self, pipeline: &Pipeline, out: &mut dyn Sink) -> i32 {
+        let mut stages = Vec::with_capacity(pipeline.len());
+        for cmd in pipeline {
+            match self.expand_words(&cmd.words) {
+                Ok(argv) => stages.push((argv, cmd.redirects.as_slice())),
+                Err(msg) => { self.report(out, &msg); return 1; }
             }
         }
+        if stages.len() > 1 { return self.exec_pipe(&stages, out); }
+        let (argv, redirects) = stages.pop().unwrap();
+        if argv.is_empty() { return 0; }
+        let mut input = std::mem::take(&mut self.input);
+        let status = self.with_redirects(redirects, &mut input, out, |sh, input, out| {
+            sh.dispatch(&argv, input, out)
+        });
+        self.input = input;
+        let _ = out.flush();
+        status
     }
 
-    fn expand_vars(&self, input: &str) -> String {
+    /// Expand `$name`, `${...}` and `$(...)` in raw text (if conditions
+    /// and for headers, which are not split into words).
+    fn expand_vars(&mut self, input: &str) -> Result<String, String> {
         let mut result = String::with_capacity(input.len());
         let chars: Vec<char> = input.chars().collect();
         let mut i = 0;
         while i < chars.len() {
-            if chars[i] == '$' && i + 1 < chars.len() && (chars[i + 1].is_alphanumeric() || chars[i + 1] == '_') {
+            if chars[i] == '$' && chars.get(i + 1) == Some(&'(')
+                && let Some(end) = syntax::subst_end(&chars, i + 2)
+            {
+                let src: String = chars[i + 2..end].iter().collect();
+                result.push_str(&self.command_output(&src));
+                i = end + 1;
+            } else if chars[i] == '$' && chars.get(i + 1) == Some(&'{')
+                && let Some(end) = syntax::brace_end(&chars, i + 2)
+            {
+                let body: String = chars[i + 2..end].iter().collect();
+                result.push_str(&self.expand_param(&body)?);
+                i = end + 1;
+            } else if chars[i] == '$' && let Some(&c @ ('?' | '@' | '#' | '!' | '0'..='9')) = chars.get(i + 1) {
+                result.push_str(&self.lookup_var(&c.to_string()).unwrap_or_default());
+                i += 2;
+            } else if chars[i] == '$' && i + 1 < chars.len() && (chars[i + 1].is_alphanumeric() || chars[i + 1] == '_') {
                 i += 1;
                 let start = i;
                 while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') { i += 1; }
                 let name: String = chars[start..i].iter().collect();
-                if let Some(val) = self.vars.get(&name) {
-                    result.push_str(val);
-                } else if let Ok(val) = env::var(&name) {
-                    result.push_str(&val);
-                } else {
-                    result.push('$');
-                    result.push_str(&name);
-                }
+                result.push_str(&self.lookup_var(&name).unwrap_or_default());
             } else {
                 result.push(chars[i]);
                 i += 1;
             }
         }
-        result
+        Ok(result)
     }
 
-    fn capture(&mut self, input: &str) -> String {
-        let parts = Self::split_args(input);
-        if parts.is_empty() { return String::new(); }
-        match Command::new(&parts[0]).args(&parts[1..]).output() {
-            Ok(o) => String::from_utf8_lossy(&o.stdout).into_owned(),
-            Err(e) => format!("Error: {}\n", e),
-        }
-    }
+    // ── Command dispatch ──────────────────────────────────────────
 
-    fn exec_pipe(&mut self, chain: &str, out: &mut BufWriter<io::StdoutLock<'_>>) {
-        let cmds: Vec<&str> = chain.split('|').collect();
-        if cmds.len() < 2 { self.dispatch(chain, out); return; }
-        let mut prev_stdout: Option<Stdio> = None;
-        let mut children = Vec::new();
-        for (i, cmd) in cmds.iter().enumerate() {
-            let parts = Self::split_args(cmd.trim());
-            if parts.is_empty() { continue; }
-            let stdin = prev_stdout.take().unwrap_or(Stdio::inherit());
-            let stdout = if i < cmds.len() - 1 { Stdio::piped() } else { Stdio::inherit() };
-            match Command::new(&parts[0]).args(&parts[1..]).stdin(stdin).stdout(stdout).spawn() {
-                Ok(child) => {
-                    if i < cmds.len() - 1 {
-                        if child.stdout.is_some() {
-                            prev_stdout = None;
-                        }
-                    }
-                    children.push(child);
-                    if let Some(ref child_out) = children.last().and_then(|c| c.stdout.as_ref()) {
-                        use std::os::windows::io::{AsRawHandle, FromRawHandle};
-                        let handle = child_out.as_raw_handle();
-                        prev_stdout = Some(unsafe { Stdio::from_raw_handle(handle) });
-                    }
-                }
-                Err(e) => { let _ = write!(out, "Error: {}\n", e); return; }
-            }
-        }
-        for mut c in children { let _ = c.wait(); }
+    fn is_builtin(&self, name: &str) -> bool {
+        BUILTINS.contains(&name) || self.functions.contains_key(name)
+            || self.vars.contains_key(&format!("_alias_{}", name))
     }
 
-    // ── Command dispatch ──────────────────────────────────────────
-
-    fn dispatch(&mut self, input: &str, out: &mut BufWriter<io::StdoutLock<'_>>) {
-        let parts = Self::split_args(input);
-        if parts.is_empty() { return; }
+    fn dispatch(&mut self, parts: &[String], stdin: &mut Input, out: &mut dyn Sink) -> i32 {
+        if parts.is_empty() { return 0; }
         let cmd = parts[0].as_str();
         let args = &parts[1..];
         let arg_str = if args.is_empty() { String::new() } else { args.join(" ") };
         match cmd {
             "exit" | "quit" => {
+                if let Some(code) = args.first() {
+                    match code.parse() {
+                        Ok(n) => self.status = n,
+                        Err(_) => { self.report(out, &format!("{}: {}: numeric argument required", cmd, code)); return 2; }
+                    }
+                }
                 let _ = out.write_all(b"\x1b[33mGoodbye.\x1b[0m\n");
                 self.running = false;
+                return self.status;
             }
             "help" => self.write_help(out),
-            "cd" => {
-                let dir = if arg_str.is_empty() {
-                    env::var("USERPROFILE").unwrap_or_else(|_| ".".into())
-                } else if arg_str == "-" {
-                    self.vars.get("OLDPWD").cloned().unwrap_or_else(|| ".".into())
-                } else {
-                    arg_str.clone()
-                };
-                let old = env::current_dir().ok().map(|p| p.to_string_lossy().into_owned());
-                if let Err(e) = env::set_current_dir(&dir) {
-                    let _ = write!(out, "cd: {}\n", e);
-                } else if let Some(old) = old {
-                    self.vars.insert("OLDPWD".into(), old);
-                }
-            }
+            // The working directory belongs to the whole process, so a
+            // pipeline stage on its own thread leaves it alone.
+            "cd" | "pushd" | "popd" | "z" if self.pipe_closed.is_some() => {}
+            "cd" => return self.builtin_cd(args, out),
+            "pushd" => return self.builtin_pushd(args, out),
+            "popd" => return self.builtin_popd(args, out),
+            "dirs" => return self.builtin_dirs(args, out),
+            "z" => return self.builtin_z(args, out),
             "let" => {
-                if let Some(eq) = arg_str.find('=') {
+                // `let x = "a "`: take the words as parsed so quoted spaces stay.
+                if args.len() >= 2 && args[1] == "=" {
+                    self.vars.insert(args[0].clone(), args[2..].join(" "));
+                } else if let Some(eq) = arg_str.find('=') {
                     let name = arg_str[..eq].trim().to_string();
                     let val = arg_str[eq + 1..].trim().to_string();
                     self.vars.insert(name, val);
                 } else {
                     let _ = out.write_all(b"Usage: let name = value\n");
+                    return 2;
                 }
             }
             "echo" => { let _ = write!(out, "{}\n", arg_str); }
             "read" => {
-                if arg_str.is_empty() { let _ = out.write_all(b"Usage: read <varname>\n"); return; }
+                if arg_str.is_empty() { let _ = out.write_all(b"Usage: read <varname>\n"); return 2; }
                 let _ = out.flush();
-                let mut input = String::new();
-                let _ = io::stdin().read_line(&mut input);
-                self.vars.insert(arg_str.clone(), input.trim().to_string());
+                let mut line = String::new();
+                let eof = stdin.reader().read_line(&mut line).unwrap_or(0) == 0;
+                self.vars.insert(arg_str.clone(), line.trim().to_string());
+                if eof { return 1; }
             }
             "sleep" => {
                 let ms: u64 = arg_str.trim().parse().unwrap_or(0);
-                if ms > 0 { thread::sleep(Duration::from_millis(ms)); }
+                let end = Instant::now() + Duration::from_millis(ms);
+                // Sleep in slices so that Ctrl-C can cut it short.
+                while let Some(left) = end.checked_duration_since(Instant::now()) && !left.is_zero() {
+                    if platform::interrupt_pending() { return 130; }
+                    thread::sleep(left.min(Duration::from_millis(50)));
+                }
             }
             "exists" => {
-                if arg_str.is_empty() { let _ = out.write_all(b"Usage: exists <path>\n"); return; }
-                let _ = write!(out, "{}\n", Path::new(arg_str.as_str()).exists());
+                if arg_str.is_empty() { let _ = out.write_all(b"Usage: exists <path>\n"); return 2; }
+                let found = Path::new(arg_str.as_str()).exists();
+                let _ = write!(out, "{}\n", found);
+                if !found { return 1; }
             }
             "count" => {
                 let dir = if arg_str.is_empty() { ".".into() } else { arg_str };
@@ -320,21 +585,23 @@ impl Shell {
                 let _ = write!(out, "{}\n", n);
             }
             "typeof" => {
-                if arg_str.is_empty() { let _ = out.write_all(b"Usage: typeof <path>\n"); return; }
+                if arg_str.is_empty() { let _ = out.write_all(b"Usage: typeof <path>\n"); return 2; }
                 let p = Path::new(arg_str.as_str());
                 if p.is_file() { let _ = out.write_all(b"file\n"); }
                 else if p.is_dir() { let _ = out.write_all(b"dir\n"); }
                 else { let _ = out.write_all(b"none\n"); }
             }
             "set" => {
-                if arg_str.is_empty() {
+                if let Some(flag @ ("-e" | "+e")) = args.first().map(String::as_str) {
+                    self.errexit = flag == "-e";
+                } else if arg_str.is_empty() {
                     for (k, v) in &self.vars {
                         let _ = write!(out, "{}={}\n", k, v);
                     }
                 } else if let Some(eq) = arg_str.find('=') {
                     let k = arg_str[..eq].trim();
                     let v = arg_str[eq + 1..].trim();
-                    unsafe { env::set_var(k, v); }
+                    if self.pipe_closed.is_none() { unsafe { env::set_var(k, v); } }
                 }
             }
             "unset" => { self.vars.remove(&arg_str as &str); }
@@ -342,116 +609,147 @@ impl Shell {
                 if let Some(eq) = arg_str.find('=') {
                     let k = arg_str[..eq].trim();
                     let v = arg_str[eq + 1..].trim();
-                    unsafe { env::set_var(k, v); }
+                    // Only the main thread touches the environment.
+                    if self.pipe_closed.is_none() { unsafe { env::set_var(k, v); } }
                     self.vars.insert(k.to_string(), v.to_string());
                 }
             }
-            "history" => {
-                for (i, h) in self.history.iter().enumerate() {
-                    let _ = write!(out, "  {} {}\n", i + 1, h);
-                }
-            }
+            "history" => return self.builtin_history(args, out),
             "pwd" => {
-                if let Ok(d) = env::current_dir() {
-                    let _ = write!(out, "{}\n", d.display());
-                }
-            }
-            "ls" => {
-                let dir = if arg_str.is_empty() { ".".into() } else { arg_str };
-                match fs::read_dir(&dir) {
-                    Ok(entries) => {
-                        let mut dirs = Vec::new();
-                        let mut files = Vec::new();
-                        for e in entries.flatten() {
-                            let n = e.file_name().to_string_lossy().into_owned();
-                            if e.path().is_dir() { dirs.push(n); } else { files.push(n); }
-                        }
-                        dirs.sort_unstable();
-                        files.sort_unstable();
-                        for d in &dirs { let _ = write!(out, " \x1b[34m{}/\x1b[0m", d); }
-                        for f in &files { let _ = write!(out, " {}", f); }
-                        if !dirs.is_empty() || !files.is_empty() { let _ = out.write_all(b"\n"); }
-                    }
-                    Err(e) => { let _ = write!(out, "ls: {}\n", e); }
-                }
-            }
-            "ll" => {
-                let dir = if arg_str.is_empty() { ".".into() } else { arg_str };
-                if let Ok(entries) = fs::read_dir(&dir) {
-                    for e in entries.flatten() {
-                        let meta = e.metadata();
-                        let size = meta.as_ref().map(|m| m.len()).unwrap_or(0);
-                        let name = e.file_name().to_string_lossy().into_owned();
-                        if e.path().is_dir() {
-                            let _ = write!(out, " \x1b[34m{:>10}  {}/\x1b[0m\n", "<DIR>", name);
-                        } else {
-                            let _ = write!(out, "  {:>10}  {}\n", size, name);
-                        }
-                    }
+                match env::current_dir() {
+                    Ok(d) => { let _ = write!(out, "{}\n", d.display()); }
+                    Err(e) => { self.report(out, &format!("pwd: {}", os_reason(&e))); return 1; }
                 }
             }
+            "ls" | "ll" => return self.builtin_ls(cmd, args, out),
             "cat" => {
-                if arg_str.is_empty() { let _ = out.write_all(b"Usage: cat <file>\n"); return; }
-                match fs::read(&arg_str) {
-                    Ok(data) => {
-                        let _ = out.write_all(&data);
-                        if data.last() != Some(&b'\n') { let _ = out.write_all(b"\n"); }
+                if args.is_empty() && stdin.is_inherit() { let _ = out.write_all(b"Usage: cat <file>...\n"); return 2; }
+                let mut status = 0;
+                let mut last = b'\n';
+                'files: for file in Self::input_files(args) {
+                    let mut reader = match Self::open_input(file, stdin) {
+                        Ok(r) => r,
+                        Err(e) => { self.report(out, &format!("cat: {}: {}", file, os_reason(&e))); status = 1; continue; }
+                    };
+                    // Copy as it arrives, so `cat` streams in a pipeline.
+                    loop {
+                        let chunk = match reader.fill_buf() {
+                            Ok([]) => break,
+                            Ok(chunk) => chunk,
+                            Err(e) => { self.report(out, &format!("cat: {}: {}", file, os_reason(&e))); status = 1; break; }
+                        };
+                        if out.write_all(chunk).is_err() { break 'files; }
+                        last = chunk[chunk.len() - 1];
+                        let n = chunk.len();
+                        reader.consume(n);
                     }
-                    Err(e) => { let _ = write!(out, "cat: {}\n", e); }
                 }
-            }
-            "head" => {
-                let (n, file) = Self::parse_num_arg(args, 10);
-                if let Ok(content) = fs::read_to_string(&file) {
-                    for line in content.lines().take(n) {
-                        let _ = write!(out, "{}\n", line);
+                if last != b'\n' { let _ = out.write_all(b"\n"); }
+                return status;
+            }
+            "head" | "tail" => {
+                let (n, files) = Self::parse_num_arg(args, 10);
+                let files = Self::input_files(files);
+                let mut status = 0;
+                for (i, file) in files.iter().enumerate() {
+                    let reader = match Self::open_input(file, stdin) {
+                        Ok(r) => r,
+                        Err(e) => { self.report(out, &format!("{}: {}: {}", cmd, file, os_reason(&e))); status = 1; continue; }
+                    };
+                    if files.len() > 1 { let _ = write!(out, "{}==> {} <==\n", if i > 0 { "\n" } else { "" }, file); }
+                    if cmd == "head" {
+                        for line in reader.lines().take(n).map_while(Result::ok) {
+                            let _ = write!(out, "{}\n", line);
+                        }
+                        continue;
                     }
-                }
-            }
-            "tail" => {
-                let (n, file) = Self::parse_num_arg(args, 10);
-                if let Ok(content) = fs::read_to_string(&file) {
-                    let lines: Vec<&str> = content.lines().collect();
+                    let lines: Vec<String> = reader.lines().map_while(Result::ok).collect();
                     let start = lines.len().saturating_sub(n);
                     for line in &lines[start..] {
                         let _ = write!(out, "{}\n", line);
                     }
                 }
+                return status;
             }
             "wc" => {
-                if let Ok(content) = fs::read_to_string(&arg_str) {
+                let mut status = 0;
+                let mut total = (0, 0, 0);
+                for file in Self::input_files(args) {
+                    let mut content = String::new();
+                    if let Err(e) = Self::open_input(file, stdin).and_then(|mut r| r.read_to_string(&mut content)) {
+                        self.report(out, &format!("wc: {}: {}", file, os_reason(&e)));
+                        status = 1;
+                        continue;
+                    }
                     let lines = content.lines().count();
                     let words = content.split_whitespace().count();
                     let bytes = content.len();
-                    let _ = write!(out, "  {}L  {}W  {}B  {}\n", lines, words, bytes, arg_str);
+                    total = (total.0 + lines, total.1 + words, total.2 + bytes);
+                    if file.is_empty() {
+                        let _ = write!(out, "  {}L  {}W  {}B\n", lines, words, bytes);
+                    } else {
+                        let _ = write!(out, "  {}L  {}W  {}B  {}\n", lines, words, bytes, file);
+                    }
+                }
+                if args.len() > 1 { let _ = write!(out, "  {}L  {}W  {}B  total\n", total.0, total.1, total.2); }
+                return status;
+            }
+            "touch" | "mkdir" | "rm" => {
+                if args.is_empty() { let _ = write!(out, "Usage: {} <path>...\n", cmd); return 2; }
+                let mut status = 0;
+                for a in args {
+                    let path = Path::new(a.as_str());
+                    let res = match cmd {
+                        "touch" => fs::OpenOptions::new().create(true).append(true).open(path).map(drop),
+                        "mkdir" => fs::create_dir_all(path),
+                        _ if path.is_dir() => fs::remove_dir_all(path),
+                        _ => fs::remove_file(path),
+                    };
+                    if let Err(e) = res {
+                        self.report(out, &format!("{}: {}: {}", cmd, a, os_reason(&e)));
+                        status = 1;
+                    }
+                }
+                return status;
+            }
+            "cp" | "mv" => {
+                if args.len() < 2 { let _ = write!(out, "Usage: {} <src> <dst>\n", cmd); return 2; }
+                let res = if cmd == "cp" { fs::copy(&args[0], &args[1]).map(drop) } else { fs::rename(&args[0], &args[1]) };
+                if let Err(e) = res {
+                    let culprit = if Path::new(&args[0]).exists() { &args[1] } else { &args[0] };
+                    self.report(out, &format!("{}: {}: {}", cmd, culprit, os_reason(&e)));
+                    return 1;
                 }
-            }
-            "touch" => { let _ = fs::OpenOptions::new().create(true).append(true).open(&arg_str); }
-            "mkdir" => { let _ = fs::create_dir_all(&arg_str); }
-            "rm" => {
-                let path = Path::new(arg_str.as_str());
-                if path.is_dir() { let _ = fs::remove_dir_all(path); }
-                else { let _ = fs::remove_file(path); }
-            }
-            "cp" => {
-                if args.len() >= 2 { let _ = fs::copy(&args[0], &args[1]); }
-                else { let _ = out.write_all(b"Usage: cp <src> <dst>\n"); }
-            }
-            "mv" => {
-                if args.len() >= 2 { let _ = fs::rename(&args[0], &args[1]); }
-                else { let _ = out.write_all(b"Usage: mv <src> <dst>\n"); }
             }
             "grep" => {
-                if args.len() < 2 { let _ = out.write_all(b"Usage: grep <pattern> <file>\n"); return; }
-                if let Ok(content) = fs::read_to_string(&args[1]) {
-                    for line in content.lines() {
-                        if line.contains(args[0].as_str()) {
-                            let highlighted = line.replace(args[0].as_str(),
-                                &format!("\x1b[31m{}\x1b[0m", args[0]));
-                            let _ = write!(out, "{}\n", highlighted);
+                let quiet = args.first().is_some_and(|a| a == "-q");
+                let args = if quiet { &args[1..] } else { args };
+                if args.is_empty() || (args.len() < 2 && stdin.is_inherit()) {
+                    let _ = out.write_all(b"Usage: grep [-q] <pattern> [file...]\n");
+                    return 2;
+                }
+                let (pattern, files) = (args[0].as_str(), &args[1..]);
+                let mut failed = false;
+                let mut matched = false;
+                'files: for file in Self::input_files(files) {
+                    let reader = match Self::open_input(file, stdin) {
+                        Ok(r) => r,
+                        Err(e) => { self.report(out, &format!("grep: {}: {}", file, os_reason(&e))); failed = true; continue; }
+                    };
+                    // With several files, each line is shown with its file name.
+                    let prefix = if files.len() > 1 { format!("{}:", file) } else { String::new() };
+                    for line in reader.lines().map_while(Result::ok) {
+                        if line.contains(pattern) {
+                            matched = true;
+                            if quiet { break 'files; }
+                            if !out.is_tty() { let _ = write!(out, "{}{}\n", prefix, line); continue; }
+                            let highlighted = line.replace(pattern, &format!("\x1b[31m{}\x1b[0m", pattern));
+                            let _ = write!(out, "{}{}\n", prefix, highlighted);
                         }
                     }
                 }
+                if failed && !(quiet && matched) { return 2; }
+                if !matched { return 1; }
             }
             "find" => {
                 let pattern = if arg_str.is_empty() { "*" } else { &arg_str };
@@ -459,15 +757,14 @@ impl Shell {
             }
             "tree" => {
                 let dir = if arg_str.is_empty() { "." } else { &arg_str };
-                Self::print_tree(Path::new(dir), "", true, out);
+                let color = out.is_tty();
+                Self::print_tree(Path::new(dir), "", true, color, out);
             }
             "whoami" => {
-                let u = env::var("USERNAME").or_else(|_| env::var("USER")).unwrap_or("unknown".into());
-                let _ = write!(out, "{}\n", u);
+                let _ = write!(out, "{}\n", platform::user_name());
             }
             "hostname" => {
-                let h = env::var("COMPUTERNAME").or_else(|_| env::var("HOSTNAME")).unwrap_or("unknown".into());
-                let _ = write!(out, "{}\n", h);
+                let _ = write!(out, "{}\n", platform::host_name());
             }
             "os" => { let _ = write!(out, "{}/{}\n", env::consts::OS, env::consts::ARCH); }
             "env" => {
@@ -476,47 +773,113 @@ impl Shell {
                 }
             }
             "time" => {
-                let (y, mo, d, h, mi, s) = unix_to_datetime(unix_secs());
+                let (y, mo, d, h, mi, s) = local_datetime(unix_secs());
                 let _ = write!(out, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}\n", y, mo, d, h, mi, s);
             }
             "date" => {
-                let (y, mo, d, _, _, _) = unix_to_datetime(unix_secs());
+                let (y, mo, d, _, _, _) = local_datetime(unix_secs());
                 let _ = write!(out, "{:04}-{:02}-{:02}\n", y, mo, d);
             }
             "calc" => {
-                if arg_str.is_empty() { let _ = out.write_all(b"Usage: calc <expr>\n"); return; }
+                if arg_str.is_empty() { let _ = out.write_all(b"Usage: calc <expr>\n"); return 2; }
                 let expr: String = arg_str.chars().filter(|c| *c != ' ').collect();
                 match eval_expr(&expr) {
                     Ok(r) if r == r.floor() && r.abs() < 1e15 => { let _ = write!(out, "{}\n", r as i64); }
                     Ok(r) => { let _ = write!(out, "{}\n", r); }
-                    Err(e) => { let _ = write!(out, "calc: {}\n", e); }
+                    Err(e) => { self.report(out, &format!("calc: {}", e)); return 1; }
                 }
             }
             "open" => {
-                if arg_str.is_empty() { let _ = out.write_all(b"Usage: open <path>\n"); return; }
+                if arg_str.is_empty() { let _ = out.write_all(b"Usage: open <path>\n"); return 2; }
                 let _ = out.flush();
-                let _ = Command::new("cmd").args(["/c", "start", &arg_str]).spawn();
+                if let Err(e) = platform::open_command(&arg_str).spawn() {
+                    self.report(out, &format!("open: {}: {}", arg_str, os_reason(&e)));
+                    return 1;
+                }
             }
             "clear" | "cls" => {
                 let _ = out.write_all(b"\x1b[2J\x1b[H");
             }
             "run" => {
-                if arg_str.is_empty() { let _ = out.write_all(b"Usage: run <script.nes>\n"); return; }
-                if let Ok(script) = fs::read_to_string(&arg_str) {
-                    let lines: Vec<String> = script.lines().map(String::from).collect();
-                    self.exec_lines(&lines, out);
-                } else {
-                    let _ = write!(out, "run: cannot read '{}'\n", arg_str);
+                let Some(path) = args.first() else {
+                    let _ = out.write_all(b"Usage: run <script.nes> [args...]\n");
+                    return 2;
+                };
+                return self.run_script(path, &args[1..], out);
+            }
+            "source" => {
+                let Some(path) = args.first() else {
+                    let _ = out.write_all(b"Usage: source <file> [args...]\n");
+                    return 2;
+                };
+                if args.len() == 1 { return self.source("source", path, out); }
+                let saved_args = std::mem::replace(&mut self.args, args[1..].to_vec());
+                let status = self.source("source", path, out);
+                self.args = saved_args;
+                return status;
+            }
+            "jobs" => return self.builtin_jobs(out),
+            "fg" => return self.builtin_fg(args, out),
+            "bg" => return self.builtin_bg(args, out),
+            "wait" => return self.builtin_wait(args, out),
+            "kill" => return self.builtin_kill(args, out),
+            "trap" => return self.builtin_trap(args, out),
+            "shift" => {
+                let n = match args.first().map(|a| a.parse::<usize>()) {
+                    None => 1,
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => { let _ = out.write_all(b"Usage: shift [n]\n"); return 2; }
+                };
+                if n > self.args.len() {
+                    self.report(out, "shift: shift count out of range");
+                    return 1;
+                }
+                self.args.drain(..n);
+            }
+            "break" | "continue" => {
+                let n = match args.first().map(|a| a.parse::<u32>()) {
+                    None => 1,
+                    Some(Ok(n)) if n > 0 => n,
+                    _ => { let _ = write!(out, "Usage: {} [n]\n", cmd); return 2; }
+                };
+                if self.loop_depth == 0 {
+                    self.report(out, &format!("{}: only meaningful in a loop", cmd));
+                    return 1;
+                }
+                let n = n.min(self.loop_depth);
+                self.flow = if cmd == "break" { Flow::Break(n) } else { Flow::Continue(n) };
+            }
+            "local" => {
+                let Some(frame) = self.locals.last_mut() else {
+                    self.report(out, "local: can only be used in a function");
+                    return 1;
+                };
+                let (name, val) = match arg_str.find('=') {
+                    Some(eq) => (arg_str[..eq].trim().to_string(), arg_str[eq + 1..].trim().to_string()),
+                    None => (arg_str.trim().to_string(), String::new()),
+                };
+                if name.is_empty() { let _ = out.write_all(b"Usage: local name [= value]\n"); return 2; }
+                if !frame.contains_key(&name) { frame.insert(name.clone(), self.vars.get(&name).cloned()); }
+                self.vars.insert(name, val);
+            }
+            "return" => {
+                if self.locals.is_empty() {
+                    self.report(out, "return: can only be used in a function");
+                    return 1;
                 }
+                let code = match args.first().map(|a| a.parse::<i32>()) {
+                    None => self.status,
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => { self.report(out, &format!("return: {}: numeric argument required", arg_str)); 2 }
+                };
+                self.flow = Flow::Return;
+                return code;
             }
             "which" => {
-                if let Ok(path) = env::var("PATH") {
-                    for dir in path.split(';') {
-                        let p = Path::new(dir).join(format!("{}.exe", arg_str));
-                        if p.exists() { let _ = write!(out, "{}\n", p.display()); return; }
-                    }
+                match platform::find_executable(&arg_str) {
+                    Some(p) => { let _ = write!(out, "{}\n", p.display()); }
+                    None => { self.report(out, &format!("which: {}: not found", arg_str)); return 1; }
                 }
-                let _ = write!(out, "which: '{}' not found\n", arg_str);
             }
             "alias" => {
                 if let Some(eq) = arg_str.find('=') {
@@ -532,91 +895,171 @@ impl Shell {
                 }
             }
             "size" => {
-                if arg_str.is_empty() { let _ = out.write_all(b"Usage: size <path>\n"); return; }
+                if arg_str.is_empty() { let _ = out.write_all(b"Usage: size <path>\n"); return 2; }
                 let path = Path::new(arg_str.as_str());
+                if !path.exists() { self.report(out, &format!("size: {}: No such file or directory", arg_str)); return 1; }
                 let total = Self::dir_size(path);
                 let _ = write!(out, "{}\n", Self::human_size(total));
             }
             "hex" => {
-                if arg_str.is_empty() { let _ = out.write_all(b"Usage: hex <file>\n"); return; }
-                if let Ok(data) = fs::read(&arg_str) {
-                    for (i, chunk) in data.chunks(16).enumerate().take(32) {
-                        let _ = write!(out, "{:08x}  ", i * 16);
-                        for b in chunk { let _ = write!(out, "{:02x} ", b); }
-                        for _ in 0..(16 - chunk.len()) { let _ = out.write_all(b"   "); }
-                        let _ = out.write_all(b" |");
-                        for &b in chunk {
-                            let c = if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' };
-                            let _ = write!(out, "{}", c);
-                        }
-                        let _ = out.write_all(b"|\n");
-                    }
-                    if data.len() > 512 {
-                        let _ = write!(out, "... ({} bytes total)\n", data.len());
+                if arg_str.is_empty() { let _ = out.write_all(b"Usage: hex <file>\n"); return 2; }
+                let data = match fs::read(&arg_str) {
+                    Ok(d) => d,
+                    Err(e) => { self.report(out, &format!("hex: {}: {}", arg_str, os_reason(&e))); return 1; }
+                };
+                for (i, chunk) in data.chunks(16).enumerate().take(32) {
+                    let _ = write!(out, "{:08x}  ", i * 16);
+                    for b in chunk { let _ = write!(out, "{:02x} ", b); }
+                    for _ in 0..(16 - chunk.len()) { let _ = out.write_all(b"   "); }
+                    let _ = out.write_all(b" |");
+                    for &b in chunk {
+                        let c = if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' };
+                        let _ = write!(out, "{}", c);
                     }
+                    let _ = out.write_all(b"|\n");
+                }
+                if data.len() > 512 {
+                    let _ = write!(out, "... ({} bytes total)\n", data.len());
                 }
             }
             _ => {
+                if let Some(func) = self.functions.get(cmd).cloned() {
+                    return self.call_function(cmd, &func, args, stdin, out);
+                }
                 let alias_key = format!("_alias_{}", cmd);
                 if let Some(expansion) = self.vars.get(&alias_key).cloned() {
-                    let full = if arg_str.is_empty() { expansion } else { format!("{} {}", expansion, arg_str) };
+                    let mut full = expansion;
+                    for a in args { full.push(' '); full.push_str(&syntax::quote(a)); }
                     self.exec(&full, out);
-                    return;
+                    return self.status;
                 }
-                let _ = out.flush();
-                let status = Command::new("cmd")
-                    .args(["/c", input])
-                    .stdin(Stdio::inherit())
-                    .stdout(Stdio::inherit())
-                    .stderr(Stdio::inherit())
-                    .status();
-                match status {
-                    Ok(s) if !s.success() => {
-                        let _ = write!(out, "\x1b[31mexit {}\x1b[0m\n", s.code().unwrap_or(-1));
+                match self.run_external(parts, stdin.take(), out) {
+                    Ok(platform::Wait::Stopped) => return jobs::stopped_status(),
+                    Ok(platform::Wait::Exited(code)) => {
+                        // A command stopped by Ctrl-C needs no report.
+                        if code != 0 && !(code == 130 && platform::interrupt_pending()) {
+                            self.write_err(out, &format!("\x1b[31mexit {}\x1b[0m\n", code));
+                        }
+                        return code;
+                    }
+                    Err(e) => {
+                        let (msg, code) = pipeline::spawn_failure(cmd, &e);
+                        self.report(out, &msg);
+                        return code;
                     }
-                    Err(_) => { let _ = write!(out, "nes: '{}' not recognized\n", cmd); }
-                    _ => {}
                 }
             }
         }
+        0
     }
 
     // ── Helpers ───────────────────────────────────────────────────
 
-    fn split_args(input: &str) -> Vec<String> {
-        let mut args = Vec::new();
-        let mut current = String::new();
-        let mut in_quote = false;
-        let mut quote_char = ' ';
-        for c in input.chars() {
-            if in_quote {
-                if c == quote_char { in_quote = false; }
-                else { current.push(c); }
-            } else if c == '"' || c == '\'' {
-                in_quote = true;
-                quote_char = c;
-            } else if c == ' ' {
-                if !current.is_empty() { args.push(std::mem::take(&mut current)); }
-            } else {
-                current.push(c);
+    /// Report a failure on stderr as `nes: <msg>`, after flushing pending
+    /// output so the two streams stay in order on the terminal.
+    fn report(&mut self, out: &mut dyn Sink, msg: &str) {
+        self.write_err(out, &format!("nes: {}\n", msg));
+    }
+
+    /// Run a `.nes` file with `params` as `$1..$n`; the caller's
+    /// positional parameters and `$0` are restored afterwards.
+    fn run_script(&mut self, path: &str, params: &[String], out: &mut dyn Sink) -> i32 {
+        let saved_args = std::mem::replace(&mut self.args, params.to_vec());
+        let saved_script = std::mem::replace(&mut self.script, path.to_string());
+        let status = self.source("run", path, out);
+        self.args = saved_args;
+        self.script = saved_script;
+        status
+    }
+
+    /// Run the lines of a file in the current shell: its variables,
+    /// aliases, functions and `cd` stay in effect. `cmd` names the
+    /// command in errors.
+    fn source(&mut self, cmd: &str, path: &str, out: &mut dyn Sink) -> i32 {
+        let script = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => { self.report(out, &format!("{}: {}: {}", cmd, path, os_reason(&e))); return 1; }
+        };
+        let lines: Vec<String> = script.lines().map(String::from).collect();
+        let saved = (self.line_base, self.lineno, self.loop_depth);
+        (self.line_base, self.loop_depth) = (0, 0);
+        self.exec_lines(&lines, out);
+        (self.line_base, self.lineno, self.loop_depth) = saved;
+        self.flow = Flow::Normal;
+        self.status
+    }
+
+    /// Run the startup files of an interactive session: `~/.nesrc`, then
+    /// `.nesrc` in the starting directory if `NES_PROJECT_RC` is set.
+    fn source_rc(&mut self, out: &mut dyn Sink) {
+        let user_rc = platform::home_dir().map(|h| Path::new(&h).join(".nesrc"));
+        if let Some(rc) = &user_rc && rc.is_file() {
+            self.source("nesrc", &rc.to_string_lossy(), out);
+        }
+        let project_rc = Path::new(".nesrc");
+        let same = user_rc.as_ref().is_some_and(|u| fs::canonicalize(u).ok() == fs::canonicalize(project_rc).ok());
+        if !project_rc.is_file() || same { return; }
+        // A checked-out repository could ship any commands in its .nesrc,
+        // so running it has to be asked for.
+        let allowed = self.vars.get("NES_PROJECT_RC").cloned().or_else(|| env::var("NES_PROJECT_RC").ok())
+            .is_some_and(|v| !v.is_empty() && v != "0");
+        if allowed {
+            self.source("nesrc", ".nesrc", out);
+        } else {
+            self.report(out, ".nesrc in this directory was not run; set NES_PROJECT_RC=1 in ~/.nesrc to allow it");
+        }
+    }
+
+    /// Run a user function with `args` as `$1..$n`. Its `local`
+    /// variables and the caller's parameters are restored afterwards.
+    fn call_function(&mut self, name: &str, func: &Function, args: &[String], stdin: &mut Input, out: &mut dyn Sink) -> i32 {
+        if self.locals.len() >= MAX_CALL_DEPTH {
+            self.report(out, &format!("{}: maximum function nesting exceeded", name));
+            return 1;
+        }
+        let saved_args = std::mem::replace(&mut self.args, args.to_vec());
+        let saved_input = std::mem::replace(&mut self.input, std::mem::take(stdin));
+        let saved_pos = (self.line_base, self.lineno, self.loop_depth);
+        (self.line_base, self.loop_depth) = (func.line, 0);
+        self.locals.push(HashMap::new());
+        self.status = 0;
+        self.exec_lines(&func.body, out);
+        if self.flow == Flow::Return { self.flow = Flow::Normal; }
+        for (name, old) in self.locals.pop().unwrap_or_default() {
+            match old {
+                Some(v) => { self.vars.insert(name, v); }
+                None => { self.vars.remove(&name); }
             }
         }
-        if !current.is_empty() { args.push(current); }
-        args
+        (self.line_base, self.lineno, self.loop_depth) = saved_pos;
+        *stdin = std::mem::replace(&mut self.input, saved_input);
+        self.args = saved_args;
+        self.status
     }
 
-    fn parse_num_arg(args: &[String], default: usize) -> (usize, String) {
-        if args.len() >= 2 {
-            let n = args[0].parse().unwrap_or(default);
-            (n, args[1].clone())
-        } else if args.len() == 1 {
-            (default, args[0].clone())
-        } else {
-            (default, String::new())
+    /// Split `head`/`tail` arguments into the line count (when the first
+    /// one is a number) and the files.
+    fn parse_num_arg(args: &[String], default: usize) -> (usize, &[String]) {
+        match args.first().and_then(|a| a.parse().ok()) {
+            Some(n) => (n, &args[1..]),
+            None => (default, args),
         }
     }
 
-    fn find_recursive(dir: &Path, pattern: &str, out: &mut impl Write) {
+    /// The files a text built-in reads: its arguments, or its input
+    /// stream (`""`) when there are none.
+    fn input_files(files: &[String]) -> Vec<&str> {
+        if files.is_empty() { vec![""] } else { files.iter().map(String::as_str).collect() }
+    }
+
+    /// Open `file` for reading, or the command's input stream when no file
+    /// (or `-`) is given.
+    fn open_input<'a>(file: &str, stdin: &'a mut Input) -> io::Result<Box<dyn BufRead + 'a>> {
+        if file.is_empty() || file == "-" { return Ok(stdin.reader()); }
+        Ok(Box::new(BufReader::new(fs::File::open(file)?)))
+    }
+
+    fn find_recursive(dir: &Path, pattern: &str, out: &mut dyn Write) {
         if let Ok(entries) = fs::read_dir(dir) {
             for e in entries.flatten() {
                 let name = e.file_name().to_string_lossy().into_owned();
@@ -629,23 +1072,23 @@ impl Shell {
         }
     }
 
-    fn print_tree(dir: &Path, prefix: &str, is_last: bool, out: &mut impl Write) {
+    fn print_tree(dir: &Path, prefix: &str, is_last: bool, color: bool, out: &mut dyn Write) {
         let name = dir.file_name().map(|n| n.to_string_lossy().into_owned())
             .unwrap_or_else(|| dir.to_string_lossy().into_owned());
         let connector = if prefix.is_empty() { "" } else if is_last { "└── " } else { "├── " };
-        if dir.is_dir() {
+        if dir.is_dir() && color {
             let _ = write!(out, "{}{}\x1b[34m{}/\x1b[0m\n", prefix, connector, name);
+        } else if dir.is_dir() {
+            let _ = write!(out, "{}{}{}/\n", prefix, connector, name);
         } else {
             let _ = write!(out, "{}{}{}\n", prefix, connector, name);
         }
-        if dir.is_dir() {
-            if let Ok(mut entries) = fs::read_dir(dir) {
-                let items: Vec<_> = entries.by_ref().flatten().collect();
-                let child_prefix = format!("{}{}", prefix,
-                    if prefix.is_empty() { "" } else if is_last { "    " } else { "│   " });
-                for (i, e) in items.iter().enumerate() {
-                    Self::print_tree(&e.path(), &child_prefix, i == items.len() - 1, out);
-                }
+        if dir.is_dir() && let Ok(mut entries) = fs::read_dir(dir) {
+            let items: Vec<_> = entries.by_ref().flatten().collect();
+            let child_prefix = format!("{}{}", prefix,
+                if prefix.is_empty() { "" } else if is_last { "    " } else { "│   " });
+            for (i, e) in items.iter().enumerate() {
+                Self::print_tree(&e.path(), &child_prefix, i == items.len() - 1, color, out);
             }
         }
     }
@@ -670,17 +1113,20 @@ impl Shell {
         else { format!("{:.1} {}", size, UNITS[unit]) }
     }
 
-    fn write_help(&self, out: &mut impl Write) {
+    fn write_help(&self, out: &mut dyn Write) {
         let _ = out.write_all(b"\x1b[33mnes\x1b[0m \xE2\x80\x94 v5.0\n\n\
 \x1b[33m NesC (Shell)\x1b[0m\n\
-\x1b[36mNavigation\x1b[0m    cd ls ll pwd tree find which\n\
+\x1b[36mNavigation\x1b[0m    cd pushd popd dirs z ls ll pwd tree find which\n\
 \x1b[36mFiles\x1b[0m         cat head tail wc touch mkdir rm cp mv hex size\n\
 \x1b[36mText\x1b[0m          echo grep\n\
 \x1b[36mSystem\x1b[0m        whoami hostname os env time date open clear\n\
-\x1b[36mShell\x1b[0m         let set unset export alias history run read\n\
-\x1b[36mControl\x1b[0m       if/else/end  for/end  sleep  exists  count  typeof\n\
+\x1b[36mShell\x1b[0m         let set unset export alias history run source read shift trap\n\
+\x1b[36mControl\x1b[0m       if/elif/else/end  match/case/end  for/while/until/end  break  continue  sleep  exists  count  typeof\n\
+\x1b[36mConditions\x1b[0m    and or not ( )  == !=  < > -eq -lt  =~  is-file is-dir empty newer-than  cmd\n\
+\x1b[36mJobs\x1b[0m          cmd &  jobs  fg [%n]  bg [%n]  wait [%n]  kill [-SIG] %n  $!\n\
+\x1b[36mFunctions\x1b[0m     fn name ... end  $0 $1 $@ $#  local  return\n\
 \x1b[36mMath\x1b[0m          calc <expr>\n\
-\x1b[36mFlow\x1b[0m          cmd1 && cmd2    cmd > file    cmd >> file    cmd | cmd\n\
+\x1b[36mFlow\x1b[0m          cmd1 && cmd2  cmd1 || cmd2  cmd > file  cmd 2>&1  cmd < file  cmd | cmd  $(cmd)\n\
 \x1b[36mOther\x1b[0m         Any unknown command runs as a system command\n\
 \x1b[36mExit\x1b[0m          exit quit\n\n\
 \x1b[33m NesT (Language)\x1b[0m    nes run <file.nest>\n\
@@ -864,6 +1310,7 @@ enum NUnOp { Neg, Not }
 
 #[derive(Clone)]
 enum NStmt {
+    #[allow(dead_code)]
     Dir(String, NVal),
     Let(String, NExpr),
     Assign(String, NExpr),
@@ -1443,22 +1890,30 @@ fn main() {
         let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
         let _ = out.write_all(b"\x1b[33mnes\x1b[0m \xE2\x80\x94 the nestea shell v5.0\n\n\
   \x1b[36mNesC (shell)\x1b[0m\n\
-  nes <command>         run a shell command\n\
-  nes enter-full        interactive shell\n\
-  nes run <file.nes>    run a NesC script\n\n\
+  nes <command>              run a shell command\n\
+  nes enter-full [--norc]    interactive shell\n\
+  nes run <file.nes> [args]  run a NesC script\n\n\
   \x1b[36mNesT (language)\x1b[0m\n\
-  nes run <file.nest>   run a NesT program\n\n\
-  nes help              show all commands\n\
-  nes --completions     list commands\n");
+  nes run <file.nest>        run a NesT program\n\n\
+  nes help                   show all commands\n\
+  nes --completions [shell]  list commands, or a bash/zsh/fish/powershell script\n");
         let _ = out.flush();
         return;
     }
     let first = &args[0];
     if first == "--completions" {
-        print!("cd\nls\nll\npwd\ntree\nfind\nwhich\ncat\nhead\ntail\nwc\ntouch\nmkdir\nrm\ncp\nmv\nhex\nsize\n\
-echo\ngrep\nwhoami\nhostname\nos\nenv\ntime\ndate\nopen\nclear\ncls\n\
-let\nset\nunset\nexport\nalias\nhistory\nrun\nread\nsleep\nexists\ncount\ntypeof\n\
-if\nfor\nend\nelse\ncalc\nhelp\nenter-full\nexit\nquit\n");
+        // No shell: the plain command list, one per line.
+        let Some(shell) = args.get(1) else {
+            let _ = io::stdout().write_all(format!("{}\nenter-full\n", BUILTINS.join("\n")).as_bytes());
+            return;
+        };
+        match complete::script(shell) {
+            Some(script) => { let _ = io::stdout().write_all(script.as_bytes()); }
+            None => {
+                eprintln!("nes: --completions: unknown shell '{}' (bash, zsh, fish, powershell)", shell);
+                std::process::exit(2);
+            }
+        }
         return;
     }
     // Check if running a .nest file
@@ -1490,65 +1945,147 @@ if\nfor\nend\nelse\ncalc\nhelp\nenter-full\nexit\nquit\n");
         return;
     }
     let mut shell = Shell::new();
+    platform::catch_interrupt();
     if first == "enter-full" {
+        shell.interactive = true;
+        platform::ignore_job_signals();
+        shell.history = history::History::load();
         {
             let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
             let _ = out.write_all(b"\x1b[33mnes\x1b[0m \xE2\x80\x94 the nestea shell v5.0 (NesC + NesT)\n\n");
             let _ = out.flush();
         }
+        if !args[1..].iter().any(|a| a == "--norc") {
+            let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
+            shell.source_rc(&mut out);
+            let _ = out.flush();
+        }
         let stdin = io::stdin();
+        // The line editor needs a terminal; piped input is read plainly.
+        let use_editor = stdin.is_terminal() && io::stdout().is_terminal()
+            && env::var("TERM").ok().is_none_or(|t| t != "dumb");
+        let mut editor = editor::Editor::default();
         let mut buf = String::with_capacity(256);
         let mut block_buf: Vec<String> = Vec::new();
         let mut block_depth: i32 = 0;
         loop {
-            {
+            let mut prompt = Vec::new();
+            if block_depth > 0 { Shell::block_prompt(&mut prompt); }
+            else {
                 let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
-                if block_depth > 0 { Shell::block_prompt(&mut out); }
-                else { shell.prompt(&mut out); }
+                shell.notify_jobs(&mut out);
+                let _ = out.flush();
+                shell.prompt(&mut prompt);
             }
             buf.clear();
-            if stdin.read_line(&mut buf).unwrap_or(0) == 0 { break; }
+            if use_editor {
+                let mut cont = Vec::new();
+                Shell::block_prompt(&mut cont);
+                let read = editor.read(&String::from_utf8_lossy(&prompt), &String::from_utf8_lossy(&cont),
+                    shell.history.entries(), |text| block_balance(text) <= 0, |line| shell.complete(line));
+                match read {
+                    Ok(Some(text)) => buf = text,
+                    _ => break,
+                }
+            } else {
+                let _ = io::stdout().write_all(&prompt);
+                let _ = io::stdout().flush();
+                if stdin.read_line(&mut buf).unwrap_or(0) == 0 { break; }
+            }
             let input = buf.trim().to_string();
             if input.is_empty() { continue; }
-            shell.history.push(input.clone());
-
-            let trimmed = input.trim();
-            let starts_block = trimmed.starts_with("if ") || trimmed.starts_with("for ");
-            let is_end = trimmed == "end";
-
-            if block_depth > 0 || starts_block {
-                if starts_block { block_depth += 1; }
-                block_buf.push(input);
-                if is_end {
-                    block_depth -= 1;
-                    if block_depth == 0 {
-                        let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
-                        shell.exec_lines(&block_buf, &mut out);
-                        let _ = out.flush();
-                        block_buf.clear();
+            let input = match shell.history.expand(&input) {
+                Ok(None) => input,
+                Ok(Some(expanded)) => { println!("{}", expanded); expanded }
+                Err(msg) => {
+                    let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
+                    shell.report(&mut out, &msg);
+                    shell.status = 1;
+                    continue;
+                }
+            };
+            // Lines typed with a leading space are kept out of the history.
+            if !buf.starts_with(' ') { shell.history.add(&input); }
+            // Ctrl-C pressed at the prompt must not cancel the new command.
+            platform::take_interrupt();
+            shell.interrupted = false;
+
+            let started = Instant::now();
+            // The editor hands over a whole block at once.
+            for line in input.lines() {
+                if !shell.running || shell.interrupted { break; }
+                let trimmed = line.trim();
+                let starts_block = opens_block(trimmed);
+                let is_end = trimmed == "end";
+
+                if block_depth > 0 || starts_block {
+                    if starts_block { block_depth += 1; }
+                    block_buf.push(line.to_string());
+                    if is_end {
+                        block_depth -= 1;
+                        if block_depth == 0 {
+                            let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
+                            shell.exec_lines(&block_buf, &mut out);
+                            let _ = out.flush();
+                            block_buf.clear();
+                        }
                     }
+                } else if !trimmed.is_empty() {
+                    let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
+                    shell.exec(trimmed, &mut out);
+                    let _ = out.flush();
                 }
-            } else {
+            }
+            shell.last_duration = started.elapsed();
+            {
+                // Run an INT trap for a Ctrl-C that ended the last command,
+                // and start the next prompt on a fresh line.
                 let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
-                shell.exec(&input, &mut out);
+                if !shell.check_signals(&mut out) && shell.interrupted && shell.status == 130 { let _ = out.write_all(b"\n"); }
                 let _ = out.flush();
             }
             if !shell.running { break; }
         }
+    } else if first == "run" && args.len() >= 2 {
+        // Pass script arguments through as-is rather than re-parsing them.
+        let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
+        shell.run_script(&args[1], &args[2..], &mut out);
+        let _ = out.flush();
     } else {
         let joined = args.join(" ");
         let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
         shell.exec(&joined, &mut out);
         let _ = out.flush();
     }
+    {
+        let mut out = BufWriter::with_capacity(4096, io::stdout().lock());
+        shell.run_exit_trap(&mut out);
+        let _ = out.flush();
+    }
+    std::process::exit(shell.status);
+}
+
+/// The OS's description of an I/O error, without the `(os error N)` suffix.
+fn os_reason(e: &io::Error) -> String {
+    let s = e.to_string();
+    match s.find(" (os error") {
+        Some(p) => s[..p].to_string(),
+        None => s,
+    }
 }
 
 fn unix_secs() -> u64 {
     SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
 }
 
-fn unix_to_datetime(mut secs: u64) -> (u64, u64, u64, u64, u64, u64) {
-    secs += 3600;
+/// Local date and time of `secs` after the epoch, as
+/// (year, month, day, hour, minute, second).
+fn local_datetime(secs: u64) -> (u64, u64, u64, u64, u64, u64) {
+    unix_to_datetime(secs.saturating_add_signed(platform::utc_offset(secs)))
+}
+
+/// The same in UTC.
+fn unix_to_datetime(secs: u64) -> (u64, u64, u64, u64, u64, u64) {
     let sec = secs % 60;
     let min = (secs / 60) % 60;
     let hour = (secs / 3600) % 24;
@@ -1574,7 +2111,7 @@ fn unix_to_datetime(mut secs: u64) -> (u64, u64, u64, u64, u64, u64) {
     (year, month, days + 1, hour, min, sec)
 }
 
-fn is_leap(y: u64) -> bool { (y % 4 == 0 && y % 100 != 0) || y % 400 == 0 }
+fn is_leap(y: u64) -> bool { (y.is_multiple_of(4) && !y.is_multiple_of(100)) || y.is_multiple_of(400) }
 
 fn eval_expr(expr: &str) -> Result<f64, &'static str> {
     let tokens = tokenize(expr.as_bytes())?;
@@ -1636,7 +2173,7 @@ fn parse_mul_div(t: &[Token], p: &mut usize) -> Result<f64, &'static str> {
 
 fn parse_power(t: &[Token], p: &mut usize) -> Result<f64, &'static str> {
     let base = parse_unary(t, p)?;
-    if *p < t.len() { if let Token::Op(b'^') = t[*p] { *p += 1; let exp = parse_power(t, p)?; return Ok(base.powf(exp)); } }
+    if *p < t.len() && let Token::Op(b'^') = t[*p] { *p += 1; let exp = parse_power(t, p)?; return Ok(base.powf(exp)); }
     Ok(base)
 }
 
@@ -1661,3 +2198,242 @@ fn parse_primary(t: &[Token], p: &mut usize) -> Result<f64, &'static str> {
         _ => Err("unexpected token"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `src` in `sh` and return what it printed.
+    fn run(sh: &mut Shell, src: &str) -> String {
+        let mut buf = Vec::new();
+        sh.exec(src, &mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    /// Run `src` as the lines of a script, with errors in the output.
+    fn script(sh: &mut Shell, src: &str) -> String {
+        let lines: Vec<String> = src.lines().map(str::to_string).collect();
+        sh.err = ErrOut::Stdout;
+        let mut buf = Vec::new();
+        sh.exec_lines(&lines, &mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn file_builtins_take_every_glob_match() {
+        let dir = env::temp_dir().join(format!("nes-logs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.log"), "A1\nB1\n").unwrap();
+        fs::write(dir.join("b.log"), "A2\n").unwrap();
+        let logs = format!("{}/*.log", dir.to_string_lossy().replace('\\', "/"));
+        let mut sh = Shell::new();
+        assert_eq!(run(&mut sh, &format!("cat {}", logs)), "A1\nB1\nA2\n");
+        let grep = run(&mut sh, &format!("grep A {}", logs));
+        assert_eq!(grep.lines().map(|l| l.rsplit('/').next().unwrap()).collect::<Vec<_>>(), ["a.log:A1", "b.log:A2"]);
+        assert_eq!(run(&mut sh, &format!("head 1 {} | grep -q B; echo $?", logs)), "1\n");
+        assert!(run(&mut sh, &format!("wc {}", logs)).ends_with("  3L  3W  9B  total\n"));
+        run(&mut sh, &format!("cat {0}/a.log {0}/missing.log", dir.display()));
+        assert_eq!(sh.status, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn pipeline_stages_leave_process_state() {
+        let mut sh = Shell::new();
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(run(&mut sh, "cd / | cat; pwd"), format!("{}\n", cwd.display()));
+        run(&mut sh, "export NES_STAGE_TEST=1 | cat");
+        assert!(env::var_os("NES_STAGE_TEST").is_none());
+    }
+
+    #[test]
+    fn while_until_break_and_continue() {
+        let mut sh = Shell::new();
+        let out = script(&mut sh, "
+            let n = 0
+            while $n < 3
+                echo w$n
+                let n = $(calc $n+1)
+            end
+            until $n == 0
+                let n = $(calc $n-1)
+                if $n == 1
+                    continue
+                end
+                echo u$n
+            end
+        ");
+        assert_eq!(out, "w0\nw1\nw2\nu2\nu0\n");
+        let out = script(&mut sh, "
+            for a in 1 2 3
+                for b in x y z
+                    if $b == y
+                        continue 2
+                    end
+                    if $a == 3
+                        break 2
+                    end
+                    echo $a$b
+                end
+                echo never
+            end
+            echo after
+        ");
+        assert_eq!(out, "1x\n2x\nafter\n");
+        assert_eq!(script(&mut sh, "while true\n    while true\n        break 5\n    end\nend\necho out"), "out\n");
+        assert_eq!(script(&mut sh, "break"), "nes: break: only meaningful in a loop\n");
+        assert_eq!(sh.status, 1);
+        assert!(script(&mut sh, "while true\n    echo x").contains("missing 'end'"));
+    }
+
+    #[test]
+    fn elif_chains() {
+        let mut sh = Shell::new();
+        let chain = "
+            if $v < 3
+                echo small
+            elif $v < 6
+                echo medium
+                if $v == 5
+                    echo five
+                else if $v == 4
+                    echo four
+                end
+            else if $v < 9
+                echo large
+            else
+                echo huge
+            end
+        ";
+        for (v, expected) in [("1", "small\n"), ("4", "medium\nfour\n"), ("5", "medium\nfive\n"), ("8", "large\n"), ("20", "huge\n")] {
+            sh.vars.insert("v".into(), v.into());
+            assert_eq!(script(&mut sh, chain), expected, "v = {}", v);
+        }
+        assert!(script(&mut sh, "if true\n    echo a\nelse\n    echo b\nelif true\nend").contains("'else' must be the last branch"));
+        assert_eq!(sh.status, 2);
+        assert!(script(&mut sh, "if true\n    echo a\nelif false").contains("missing 'end' for 'if'"));
+    }
+
+    #[test]
+    fn functions() {
+        let mut sh = Shell::new();
+        let out = script(&mut sh, "
+            fn look
+                for i in range 1 9
+                    while true
+                        if $i == $1
+                            return 7
+                        end
+                        break
+                    end
+                end
+                return 1
+            end
+            look 4
+            echo ret=$?
+            look 12
+            echo ret=$?
+            fn fact
+                if $1 <= 1
+                    echo 1
+                    return
+                end
+                local prev = $(fact $(calc $1-1))
+                calc $1*$prev
+            end
+            echo $(fact 5)
+            let x = outer
+            fn shadow
+                local x = inner
+                local fresh = 1
+                echo $x $# $@
+            end
+            shadow a \"b c\"
+            echo $x ${fresh:-unset} $#
+        ");
+        assert_eq!(out, "ret=7\nret=1\n120\ninner 2 a b c\nouter unset 0\n");
+        assert_eq!(sh.loop_depth, 0);
+        // Test threads get a smaller stack than the main thread.
+        let (out, mut sh) = thread::Builder::new().stack_size(8 << 20).spawn(move || {
+            (script(&mut sh, "fn forever\n    forever\nend\nforever\necho status=$?"), sh)
+        }).unwrap().join().unwrap();
+        assert_eq!(out, "nes: forever: maximum function nesting exceeded\nstatus=1\n");
+        assert!(sh.locals.is_empty());
+        assert_eq!(script(&mut sh, "return"), "nes: return: can only be used in a function\n");
+    }
+
+    #[test]
+    fn match_blocks() {
+        let mut sh = Shell::new();
+        let out = script(&mut sh, "
+            let ext = rs
+            for w in main.rs Cargo.toml b \"*\" lib.$ext x y
+                match $w
+                    case *.$ext|*.toml
+                        echo $w: source
+                    case b
+                        echo $w: bee
+                    case \"*\"
+                        echo $w: star
+                    default
+                        match $w
+                            case [a-z]
+                                echo $w: letter
+                                break
+                        end
+                end
+            end
+            match nothing
+                case other
+                    echo no
+            end
+            echo done
+        ");
+        assert_eq!(out, "main.rs: source\nCargo.toml: source\nb: bee\n*: star\nlib.rs: source\nx: letter\ndone\n");
+        assert!(sh.case_matches("'a*'", "a*").unwrap());
+        assert!(!sh.case_matches("'a*'", "abc").unwrap());
+        assert!(sh.case_matches("x | ?b? ", "abc").unwrap());
+    }
+
+    #[test]
+    fn calendar_dates() {
+        assert_eq!(unix_to_datetime(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(unix_to_datetime(951_782_400), (2000, 2, 29, 0, 0, 0));
+        assert_eq!(unix_to_datetime(1_700_000_000), (2023, 11, 14, 22, 13, 20));
+    }
+
+    #[test]
+    fn script_arguments() {
+        let dir = env::temp_dir().join(format!("nes-args-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("args.nes");
+        fs::write(&path, "echo $0\necho $# $1 $@\nfor a in \"$@\"\n    echo [$a]\nend\nshift\necho $# $1\nshift 2\necho $? ${10}\nshift 5\n").unwrap();
+        let mut sh = Shell::new();
+        sh.err = ErrOut::Stdout;
+        (sh.args, sh.script) = (vec!["outer".into()], "main".into());
+        let out = run(&mut sh, &format!("run {} a 'b c' d", path.display()));
+        assert_eq!(out, format!("{}\n3 a a b c d\n[a]\n[b c]\n[d]\n2 b c\n0\nnes: shift: shift count out of range\n", path.display()));
+        assert_eq!(run(&mut sh, "echo $0 $# $1"), "main 1 outer\n");
+        sh.args = (1..=11).map(|n| n.to_string()).collect();
+        assert_eq!(run(&mut sh, "echo ${10} $11; shift x"), "10 11\nUsage: shift [n]\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sourcing() {
+        let dir = env::temp_dir().join(format!("nes-source-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rc = dir.join("settings.nes");
+        fs::write(&rc, "let greeting = hello\nalias hi = echo $greeting\nfn twice\n    echo $1 $1\nend\necho sourced $# $1\n").unwrap();
+        let mut sh = Shell::new();
+        sh.err = ErrOut::Stdout;
+        sh.args = vec!["outer".into()];
+        assert_eq!(run(&mut sh, &format!("source {}", rc.display())), "sourced 1 outer\n");
+        assert_eq!(run(&mut sh, &format!("source {} a b", rc.display())), "sourced 2 a\n");
+        assert_eq!(run(&mut sh, "echo $# $1; hi; twice x"), "1 outer\nhello\nx x\n");
+        let missing = dir.join("missing.nes");
+        assert!(run(&mut sh, &format!("source {}", missing.display())).starts_with(&format!("nes: source: {}: ", missing.display())));
+        assert_eq!(sh.status, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
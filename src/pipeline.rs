@@ -0,0 +1,472 @@
+// ══════════════════════════════════════════════════════════════════
+// Pipelines — stream plumbing between built-ins and external programs
+// ══════════════════════════════════════════════════════════════════
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, IsTerminal, PipeReader, PipeWriter, Read, Write};
+use std::mem;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+
+use crate::jobs::stopped_status;
+use crate::platform::{self, Wait};
+use crate::syntax::{RedirOp, Redirect};
+use crate::{os_reason, Shell};
+
+/// Where a command reads its standard input from.
+#[derive(Default)]
+pub enum Input {
+    /// The shell's own stdin (usually the terminal).
+    #[default]
+    Inherit,
+    /// Output buffered from a built-in stage.
+    Bytes(Cursor<Vec<u8>>),
+    /// Output of an external stage, still being produced.
+    Pipe(BufReader<PipeReader>),
+    /// A file opened by `< file`.
+    File(BufReader<fs::File>),
+    /// Input of a built-in stage running on its own thread. It reads as
+    /// ended once the stage's output has lost its reader.
+    Stage(Box<Input>, Arc<AtomicBool>),
+}
+
+impl Input {
+    pub fn is_inherit(&self) -> bool { matches!(self, Input::Inherit) }
+
+    /// Take the stream for a command that will consume it, leaving
+    /// end-of-file behind (or the terminal, if that is what it was).
+    pub fn take(&mut self) -> Input {
+        let empty = if self.is_inherit() { Input::Inherit } else { Input::Bytes(Cursor::default()) };
+        mem::replace(self, empty)
+    }
+
+    /// Line-oriented reader for built-ins.
+    pub fn reader(&mut self) -> Box<dyn BufRead + '_> {
+        match self {
+            Input::Inherit => Box::new(io::stdin().lock()),
+            Input::Bytes(c) => Box::new(c),
+            Input::Pipe(p) => Box::new(p),
+            Input::File(f) => Box::new(f),
+            Input::Stage(inner, closed) => Box::new(StageReader { inner: inner.reader(), closed }),
+        }
+    }
+
+    /// Hand the stream to an external process. Buffered bytes cannot be
+    /// passed as a handle, so they come back to be fed through a pipe.
+    fn into_stdio(self) -> (Stdio, Option<Vec<u8>>) {
+        match self {
+            Input::Inherit => (Stdio::inherit(), None),
+            Input::Bytes(c) => {
+                let pos = c.position() as usize;
+                let mut data = c.into_inner();
+                data.drain(..pos.min(data.len()));
+                (Stdio::piped(), Some(data))
+            }
+            Input::Pipe(p) if p.buffer().is_empty() => (Stdio::from(p.into_inner()), None),
+            Input::File(f) if f.buffer().is_empty() => (Stdio::from(f.into_inner()), None),
+            Input::Stage(inner, _) => inner.into_stdio(),
+            mut other => {
+                let mut data = Vec::new();
+                let _ = other.reader().read_to_end(&mut data);
+                (Stdio::piped(), Some(data))
+            }
+        }
+    }
+}
+
+/// Reader for [`Input::Stage`], at end-of-file once `closed` is raised.
+struct StageReader<'a> {
+    inner: Box<dyn BufRead + 'a>,
+    closed: &'a AtomicBool,
+}
+
+impl Read for StageReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.closed.load(Ordering::SeqCst) { return Ok(0); }
+        self.inner.read(buf)
+    }
+}
+
+impl BufRead for StageReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.closed.load(Ordering::SeqCst) { return Ok(&[]); }
+        self.inner.fill_buf()
+    }
+    fn consume(&mut self, n: usize) { self.inner.consume(n) }
+}
+
+/// An output destination a command can write to. `stdio` gives an
+/// external process a matching handle, or `None` when its output has to
+/// be piped back through the shell and written here.
+pub trait Sink: Write {
+    fn stdio(&mut self) -> Option<Stdio>;
+    /// Whether output lands on a terminal (colours, column layout).
+    fn is_tty(&self) -> bool { false }
+}
+
+impl Sink for BufWriter<io::StdoutLock<'_>> {
+    fn stdio(&mut self) -> Option<Stdio> {
+        let _ = self.flush();
+        Some(Stdio::from(io::stdout()))
+    }
+    fn is_tty(&self) -> bool { io::stdout().is_terminal() }
+}
+
+impl Sink for Vec<u8> {
+    fn stdio(&mut self) -> Option<Stdio> { None }
+}
+
+impl Sink for fs::File {
+    fn stdio(&mut self) -> Option<Stdio> { self.try_clone().ok().map(Stdio::from) }
+}
+
+/// Output of a built-in stage running on its own thread. When the next
+/// stage stops reading, `closed` is raised so the stage stops as well.
+struct StageSink {
+    pipe: PipeWriter,
+    closed: Arc<AtomicBool>,
+}
+
+impl Write for StageSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipe.write(buf).inspect_err(|e| {
+            if e.kind() == io::ErrorKind::BrokenPipe { self.closed.store(true, Ordering::SeqCst); }
+        })
+    }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+impl Sink for StageSink {
+    fn stdio(&mut self) -> Option<Stdio> { self.pipe.try_clone().ok().map(Stdio::from) }
+}
+
+/// Stack for a built-in stage's thread, as large as the main thread's so
+/// deep function calls fail the same way in a pipeline.
+const STAGE_STACK: usize = 8 << 20;
+
+/// Where diagnostics go: built-in error messages and external stderr.
+#[derive(Default)]
+pub enum ErrOut {
+    #[default]
+    Terminal,
+    /// `2> file` / `2>> file`.
+    File(fs::File),
+    /// `2>&1` / `&>`: wherever the command's stdout goes.
+    Stdout,
+}
+
+impl ErrOut {
+    fn try_clone(&self) -> io::Result<ErrOut> {
+        Ok(match self {
+            ErrOut::Terminal => ErrOut::Terminal,
+            ErrOut::File(f) => ErrOut::File(f.try_clone()?),
+            ErrOut::Stdout => ErrOut::Stdout,
+        })
+    }
+}
+
+/// Files opened for one command's redirections.
+#[derive(Default)]
+pub struct Redirs {
+    pub input: Option<Input>,
+    pub out: Option<fs::File>,
+    pub err: Option<ErrOut>,
+}
+
+/// A running external process plus the thread feeding it buffered input.
+pub struct Job {
+    pub child: Child,
+    feeder: Option<JoinHandle<()>>,
+}
+
+impl Job {
+    pub fn wait(mut self) -> io::Result<ExitStatus> {
+        platform::set_foreground(self.child.id());
+        let status = self.child.wait();
+        platform::set_foreground(0);
+        if let Some(f) = self.feeder.take() { let _ = f.join(); }
+        status
+    }
+
+    /// Let the feeder finish once the process has been reaped elsewhere.
+    pub fn finish(mut self) {
+        if let Some(f) = self.feeder.take() { let _ = f.join(); }
+    }
+}
+
+/// The process that runs `parts`. Programs are spawned directly, so
+/// their arguments never pass through another shell; on Windows, names
+/// that are not on `PATH` (e.g. `dir`) go through `cmd /C`.
+pub fn command(parts: &[String]) -> Command {
+    #[cfg(windows)]
+    if platform::find_executable(&parts[0]).is_none() { return platform::system_shell(parts); }
+    let mut c = Command::new(&parts[0]);
+    c.args(&parts[1..]);
+    c
+}
+
+/// Message and status for a command that could not be started: 127 when
+/// there is no such program, 126 when it exists but cannot be run.
+pub fn spawn_failure(name: &str, e: &io::Error) -> (String, i32) {
+    match e.kind() {
+        io::ErrorKind::NotFound => (format!("{}: command not found", name), 127),
+        _ => (format!("{}: {}", name, os_reason(e)), 126),
+    }
+}
+
+/// Spawn an external command.
+pub fn spawn_command(mut cmd: Command, input: Input, stdout: Stdio, stderr: Stdio) -> io::Result<Job> {
+    let (stdin, feed) = input.into_stdio();
+    let mut child = cmd.stdin(stdin).stdout(stdout).stderr(stderr).spawn()?;
+    let feeder = match (feed, child.stdin.take()) {
+        (Some(data), Some(mut pipe)) => Some(thread::spawn(move || { let _ = pipe.write_all(&data); })),
+        _ => None,
+    };
+    Ok(Job { child, feeder })
+}
+
+impl Shell {
+    /// Write diagnostics to the current stderr target.
+    pub fn write_err(&mut self, out: &mut dyn Sink, text: &str) {
+        let _ = out.flush();
+        let _ = match &mut self.err {
+            ErrOut::Terminal => io::stderr().write_all(text.as_bytes()),
+            ErrOut::File(f) => f.write_all(text.as_bytes()),
+            ErrOut::Stdout => out.write_all(text.as_bytes()),
+        };
+    }
+
+    /// stdout and stderr handles for an external process writing to `out`,
+    /// or to a fresh pipe when there is no `out` (a middle pipeline stage)
+    /// or `out` cannot take a handle. The pipe's read end is returned so
+    /// the caller can drain it.
+    pub fn output_stdio(&mut self, out: Option<&mut dyn Sink>) -> io::Result<(Stdio, Stdio, Option<PipeReader>)> {
+        let direct = out.and_then(|o| o.stdio().map(|s| (s, o.stdio())));
+        let (stdout, same, reader) = match direct {
+            Some((s, again)) => (s, again, None),
+            None => {
+                let (r, w) = io::pipe()?;
+                (Stdio::from(w.try_clone()?), Some(Stdio::from(w)), Some(r))
+            }
+        };
+        let stderr = match &self.err {
+            ErrOut::Terminal => Stdio::inherit(),
+            ErrOut::File(f) => Stdio::from(f.try_clone()?),
+            ErrOut::Stdout => same.unwrap_or_else(Stdio::inherit),
+        };
+        Ok((stdout, stderr, reader))
+    }
+
+    /// Whether a foreground pipeline of programs writing to `out` runs
+    /// with job control: in a process group of its own that is given the
+    /// terminal, so Ctrl-Z stops it and not the shell. Interactive
+    /// sessions use it unless a stage is a built-in, which runs in the
+    /// shell, or the output is captured through the shell.
+    fn job_control_for(&self, stages: &[&[String]], out: &mut dyn Sink) -> bool {
+        self.job_control()
+            && stages.iter().all(|p| p.is_empty() || !self.is_builtin(&p[0]))
+            && out.stdio().is_some()
+    }
+
+    /// Spawn one program of a foreground pipeline. With job control the
+    /// first one leads a new process group, which the others join and
+    /// which is handed the terminal.
+    fn spawn_foreground(
+        &mut self, parts: &[String], input: Input, stdout: Stdio, stderr: Stdio, control: bool, pgid: &mut u32,
+    ) -> io::Result<Job> {
+        let mut cmd = command(parts);
+        if control { platform::set_process_group(&mut cmd, *pgid, true); }
+        let job = spawn_command(cmd, input, stdout, stderr)?;
+        if control && *pgid == 0 {
+            *pgid = job.child.id();
+            platform::give_terminal(Some(*pgid));
+        }
+        Ok(job)
+    }
+
+    /// Spawn an external command whose output goes to `out`, and wait for
+    /// it to finish, or to stop on Ctrl-Z.
+    pub fn run_external(&mut self, parts: &[String], input: Input, out: &mut dyn Sink) -> io::Result<Wait> {
+        let control = self.job_control_for(&[parts], out);
+        let (stdout, stderr, reader) = self.output_stdio(Some(&mut *out))?;
+        let job = self.spawn_foreground(parts, input, stdout, stderr, control, &mut 0)?;
+        if let Some(mut r) = reader { let _ = io::copy(&mut r, out); }
+        Ok(self.wait_foreground(&[parts], vec![job], control, out))
+    }
+
+    /// Open the files named by a command's redirections. Reports the
+    /// first one that cannot be expanded or opened and returns `None`.
+    pub fn open_redirects(&mut self, redirects: &[Redirect], out: &mut dyn Sink) -> Option<Redirs> {
+        let mut redirs = Redirs::default();
+        for r in redirects {
+            let mut opts = fs::OpenOptions::new();
+            match r.op {
+                RedirOp::ErrToOut => { redirs.err = Some(ErrOut::Stdout); continue; }
+                RedirOp::Read => { opts.read(true); }
+                RedirOp::Write | RedirOp::ErrWrite | RedirOp::AllWrite => { opts.write(true).create(true).truncate(true); }
+                RedirOp::Append | RedirOp::ErrAppend | RedirOp::AllAppend => { opts.append(true).create(true); }
+            }
+            let path = match self.expand_words(std::slice::from_ref(&r.target)) {
+                Ok(words) => words.join(" "),
+                Err(msg) => { self.report(out, &msg); return None; }
+            };
+            let file = match opts.open(&path) {
+                Ok(f) => f,
+                Err(e) => {
+                    self.report(out, &format!("{}: {}", path, os_reason(&e)));
+                    return None;
+                }
+            };
+            match r.op {
+                RedirOp::Read => redirs.input = Some(Input::File(BufReader::new(file))),
+                RedirOp::Write | RedirOp::Append => redirs.out = Some(file),
+                RedirOp::ErrWrite | RedirOp::ErrAppend => redirs.err = Some(ErrOut::File(file)),
+                RedirOp::AllWrite | RedirOp::AllAppend => { redirs.out = Some(file); redirs.err = Some(ErrOut::Stdout); }
+                RedirOp::ErrToOut => unreachable!(),
+            }
+        }
+        Some(redirs)
+    }
+
+    /// Run `f` with a command's redirections applied: `<` replaces its
+    /// input, `>`/`>>` its output and `2>`/`2>&1`/`&>` the shell's stderr
+    /// target, which is restored afterwards.
+    pub fn with_redirects(
+        &mut self,
+        redirects: &[Redirect],
+        input: &mut Input,
+        out: &mut dyn Sink,
+        f: impl FnOnce(&mut Self, &mut Input, &mut dyn Sink) -> i32,
+    ) -> i32 {
+        if redirects.is_empty() { return f(self, input, out); }
+        let Some(mut redirs) = self.open_redirects(redirects, out) else { return 1 };
+        let saved = redirs.err.take().map(|e| mem::replace(&mut self.err, e));
+        let input = match redirs.input.as_mut() { Some(i) => i, None => input };
+        let status = match redirs.out.as_mut() {
+            Some(file) => f(self, input, file),
+            None => f(self, input, out),
+        };
+        if let Some(e) = saved { self.err = e; }
+        status
+    }
+
+    /// A copy of the shell for a built-in pipeline stage: variables,
+    /// functions and parameters carry over, but nothing it changes comes
+    /// back. Built-ins that would change the working directory, the
+    /// environment or signal handlers do nothing there (see `dispatch`).
+    /// It stops once `closed` is raised.
+    fn subshell(&self, closed: Arc<AtomicBool>) -> io::Result<Shell> {
+        let mut sub = Shell::new();
+        sub.vars = self.vars.clone();
+        sub.functions = self.functions.clone();
+        sub.args = self.args.clone();
+        sub.script = self.script.clone();
+        sub.errexit = self.errexit;
+        sub.status = self.status;
+        sub.dir_stack = self.dir_stack.clone();
+        sub.err = self.err.try_clone()?;
+        sub.pipe_closed = Some(closed);
+        Ok(sub)
+    }
+
+    /// Start a built-in stage that is not the last one on its own thread,
+    /// writing into a pipe, and return the pipe's read end.
+    fn spawn_builtin_stage(&mut self, parts: &[String], input: Input) -> io::Result<(JoinHandle<()>, PipeReader)> {
+        let (reader, pipe) = io::pipe()?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let mut sub = self.subshell(closed.clone())?;
+        let mut input = Input::Stage(Box::new(input), closed.clone());
+        let parts = parts.to_vec();
+        let handle = thread::Builder::new().stack_size(STAGE_STACK).spawn(move || {
+            let mut out = StageSink { pipe, closed };
+            sub.dispatch(&parts, &mut input, &mut out);
+        })?;
+        Ok((handle, reader))
+    }
+
+    /// Run `a | b | c` with every stage running at once. External stages
+    /// are connected to each other directly through OS pipes; a built-in
+    /// stage before the last runs on its own thread in a copy of the
+    /// shell, writing into a pipe. A stage whose output is redirected to
+    /// a file passes nothing on.
+    pub fn exec_pipe(&mut self, stages: &[(Vec<String>, &[Redirect])], out: &mut dyn Sink) -> i32 {
+        let argvs: Vec<&[String]> = stages.iter().map(|(p, _)| p.as_slice()).collect();
+        let control = self.job_control_for(&argvs, out);
+        let mut pgid = 0;
+        let mut input = self.input.take();
+        let mut jobs = Vec::with_capacity(stages.len());
+        let mut threads = Vec::new();
+        let mut status = 0;
+        for (i, (parts, redirects)) in stages.iter().enumerate() {
+            if parts.is_empty() { continue; }
+            let last = i + 1 == stages.len();
+            let Some(redirs) = self.open_redirects(redirects, out) else {
+                input = Input::Bytes(Cursor::default());
+                if last { status = 1; }
+                continue;
+            };
+            let saved = redirs.err.map(|e| mem::replace(&mut self.err, e));
+            if let Some(i) = redirs.input { input = i; }
+            if self.is_builtin(&parts[0]) {
+                if let Some(mut file) = redirs.out {
+                    let code = self.dispatch(parts, &mut input, &mut file);
+                    if last { status = code; }
+                    input = Input::Bytes(Cursor::default());
+                } else if last {
+                    status = self.dispatch(parts, &mut input, out);
+                } else {
+                    match self.spawn_builtin_stage(parts, mem::take(&mut input)) {
+                        Ok((handle, reader)) => { threads.push(handle); input = Input::Pipe(BufReader::new(reader)); }
+                        Err(e) => {
+                            if let Some(e) = saved { self.err = e; }
+                            self.report(out, &format!("{}: {}", parts[0], os_reason(&e)));
+                            status = 1;
+                            break;
+                        }
+                    }
+                }
+            } else {
+                let prev = mem::take(&mut input);
+                let wiring = match (redirs.out, last) {
+                    (Some(mut file), _) => self.output_stdio(Some(&mut file)),
+                    (None, true) => self.output_stdio(Some(&mut *out)),
+                    (None, false) => self.output_stdio(None),
+                };
+                let started = wiring.and_then(|(o, e, r)| Ok((self.spawn_foreground(parts, prev, o, e, control, &mut pgid)?, r)));
+                match started {
+                    Ok((job, reader)) => {
+                        match reader {
+                            Some(r) if last => { let _ = io::copy(&mut BufReader::new(r), out); }
+                            Some(r) => input = Input::Pipe(BufReader::new(r)),
+                            None if !last => input = Input::Bytes(Cursor::default()),
+                            None => {}
+                        }
+                        jobs.push((last, job));
+                    }
+                    Err(e) => {
+                        if let Some(e) = saved { self.err = e; }
+                        let (msg, code) = spawn_failure(&parts[0], &e);
+                        self.report(out, &msg);
+                        status = code;
+                        break;
+                    }
+                }
+            }
+            if let Some(e) = saved { self.err = e; }
+        }
+        drop(input);
+        for t in threads { let _ = t.join(); }
+        if let Some(&(last, _)) = jobs.last() {
+            let jobs = jobs.into_iter().map(|(_, job)| job).collect();
+            let code = match self.wait_foreground(&argvs, jobs, control, out) {
+                Wait::Exited(code) => code,
+                Wait::Stopped => stopped_status(),
+            };
+            if last { status = code; }
+        }
+        let _ = out.flush();
+        status
+    }
+}
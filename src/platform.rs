@@ -0,0 +1,701 @@
+// ══════════════════════════════════════════════════════════════════
+// Platform layer — everything that differs between Windows and Unix
+// ══════════════════════════════════════════════════════════════════
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// `cmd /C` running `parts`, for names that are not programs on `PATH`
+/// (`dir`, `copy` and the other `cmd` built-ins). The line is passed
+/// raw, each argument quoted so `cmd` reads it back as one word and its
+/// metacharacters as text; `%` uses the `%%cd:~,%` trick, since `cmd`
+/// expands variables even inside quotes.
+#[cfg(windows)]
+pub fn system_shell(parts: &[String]) -> Command {
+    use std::os::windows::process::CommandExt;
+    let quote = |arg: &str| {
+        if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"&|<>^%(),;=".contains(c)) {
+            return arg.to_string();
+        }
+        let mut q = String::from('"');
+        for c in arg.chars() {
+            match c {
+                '"' => q.push_str("\"\""),
+                '%' => q.push_str("%%cd:~,%"),
+                c => q.push(c),
+            }
+        }
+        q.push('"');
+        q
+    };
+    let line: Vec<String> = parts.iter().map(|p| quote(p)).collect();
+    let mut c = Command::new("cmd");
+    // `/S` strips exactly the outer quotes; `/V:OFF` keeps `!` literal.
+    c.raw_arg(format!("/D /V:OFF /S /C \"{}\"", line.join(" ")));
+    c
+}
+
+/// Command that opens a path or URL with the desktop's default handler.
+#[cfg(windows)]
+pub fn open_command(target: &str) -> Command {
+    let mut c = Command::new("cmd");
+    c.args(["/c", "start", "", target]);
+    c
+}
+
+#[cfg(target_os = "macos")]
+pub fn open_command(target: &str) -> Command {
+    let mut c = Command::new("open");
+    c.arg(target);
+    c
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn open_command(target: &str) -> Command {
+    let mut c = Command::new("xdg-open");
+    c.arg(target);
+    c
+}
+
+/// The user's home directory (`USERPROFILE` on Windows, `HOME` elsewhere).
+pub fn home_dir() -> Option<String> {
+    let primary = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    env::var(primary).ok().filter(|h| !h.is_empty())
+        .or_else(|| env::var(if cfg!(windows) { "HOME" } else { "USERPROFILE" }).ok())
+}
+
+/// Home directory of another user, for `~name`: their `/etc/passwd`
+/// entry on Unix, a sibling of the current user's profile on Windows.
+#[cfg(unix)]
+pub fn user_home(name: &str) -> Option<String> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().map(|l| l.split(':').collect::<Vec<_>>())
+        .find(|f| f.len() > 5 && f[0] == name)
+        .map(|f| f[5].to_string())
+}
+
+#[cfg(windows)]
+pub fn user_home(name: &str) -> Option<String> {
+    let profiles = Path::new(&home_dir()?).parent()?.join(name);
+    profiles.is_dir().then(|| profiles.to_string_lossy().into_owned())
+}
+
+pub fn user_name() -> String {
+    env::var("USERNAME").or_else(|_| env::var("USER")).unwrap_or("unknown".into())
+}
+
+/// The machine's name; `HOSTNAME` is usually a shell variable rather than
+/// exported, so Unix falls back to `/etc/hostname`.
+pub fn host_name() -> String {
+    env::var("COMPUTERNAME").or_else(|_| env::var("HOSTNAME")).ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok().map(|h| h.trim().to_string()).filter(|h| !h.is_empty()))
+        .unwrap_or("unknown".into())
+}
+
+/// Directory for Nes's own files: `%APPDATA%\nes` on Windows,
+/// `$XDG_CONFIG_HOME/nes` or `~/.config/nes` elsewhere.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()).map(PathBuf::from)
+            .or_else(|| home_dir().map(|h| Path::new(&h).join(".config")))
+    };
+    base.map(|b| b.join("nes"))
+}
+
+/// Candidate file names for an executable: `PATHEXT` variants on Windows,
+/// the bare name on Unix.
+#[cfg(windows)]
+fn executable_names(name: &str) -> Vec<String> {
+    if Path::new(name).extension().is_some() { return vec![name.to_string()]; }
+    let exts = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+    exts.split(';').filter(|e| !e.is_empty())
+        .map(|e| format!("{}{}", name, e.to_ascii_lowercase()))
+        .collect()
+}
+
+#[cfg(unix)]
+fn executable_names(name: &str) -> Vec<String> {
+    vec![name.to_string()]
+}
+
+#[cfg(windows)]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+/// Name a file in a `PATH` directory is run by, if it is executable:
+/// `cargo` for `cargo.exe` on Windows, the file name on Unix.
+#[cfg(windows)]
+pub fn command_name(path: &Path) -> Option<String> {
+    let ext = path.extension()?.to_str()?.to_ascii_uppercase();
+    let exts = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into()).to_ascii_uppercase();
+    if !exts.split(';').any(|e| e.strip_prefix('.') == Some(&ext)) || !path.is_file() { return None; }
+    path.file_stem().map(|s| s.to_string_lossy().into_owned())
+}
+
+#[cfg(unix)]
+pub fn command_name(path: &Path) -> Option<String> {
+    if !is_executable(path) { return None; }
+    path.file_name().map(|s| s.to_string_lossy().into_owned())
+}
+
+/// Locate `name` on `PATH`, the way the system would when spawning it.
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    if name.contains('/') || name.contains(std::path::MAIN_SEPARATOR) {
+        let p = PathBuf::from(name);
+        return if is_executable(&p) { Some(p) } else { None };
+    }
+    let path = env::var_os("PATH")?;
+    for dir in env::split_paths(&path) {
+        for candidate in executable_names(name) {
+            let p = dir.join(candidate);
+            if is_executable(&p) { return Some(p); }
+        }
+    }
+    None
+}
+
+/// Permissions column of `ls -l`: `drwxr-xr-x` on Unix, and on Windows
+/// the type followed by the read-only, hidden and archive attributes.
+#[cfg(unix)]
+pub fn mode_string(meta: &std::fs::Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = meta.permissions().mode();
+    let kind = if meta.is_symlink() { 'l' } else if meta.is_dir() { 'd' } else { '-' };
+    let mut s = String::from(kind);
+    for shift in [6, 3, 0] {
+        let bits = mode >> shift;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+#[cfg(windows)]
+pub fn mode_string(meta: &std::fs::Metadata) -> String {
+    use std::os::windows::fs::MetadataExt;
+    let attrs = meta.file_attributes();
+    let kind = if meta.is_symlink() { 'l' } else if meta.is_dir() { 'd' } else { '-' };
+    let flag = |bit: u32, c: char| if attrs & bit != 0 { c } else { '-' };
+    [kind, flag(0x1, 'r'), flag(0x2, 'h'), flag(0x20, 'a')].iter().collect()
+}
+
+/// Whether a directory entry is hidden: a dot file, or on Windows one
+/// with the hidden attribute.
+#[cfg(unix)]
+pub fn is_hidden(name: &str, _meta: &std::fs::Metadata) -> bool {
+    name.starts_with('.')
+}
+
+#[cfg(windows)]
+pub fn is_hidden(name: &str, meta: &std::fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    name.starts_with('.') || meta.file_attributes() & 0x2 != 0
+}
+
+/// Numeric exit status of a finished process. On Unix a process killed by
+/// a signal reports `128 + signal`, as other shells do.
+#[cfg(unix)]
+pub fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status.code().or_else(|| status.signal().map(|s| 128 + s)).unwrap_or(1)
+}
+
+#[cfg(windows)]
+pub fn exit_code(status: ExitStatus) -> i32 {
+    status.code().unwrap_or(1)
+}
+
+// ── Processes and signals ─────────────────────────────────────
+
+/// What waiting on a child process found.
+pub enum Wait {
+    Exited(i32),
+    /// Stopped by a signal (Ctrl-Z, `kill -STOP`); Unix only.
+    #[cfg_attr(windows, allow(dead_code))]
+    Stopped,
+}
+
+/// Signal names accepted by `kill`, with their numbers on this system.
+#[cfg(target_os = "macos")]
+pub const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", 1), ("INT", 2), ("QUIT", 3), ("KILL", 9), ("USR1", 30), ("USR2", 31),
+    ("TERM", 15), ("CONT", 19), ("STOP", 17), ("TSTP", 18),
+];
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", 1), ("INT", 2), ("QUIT", 3), ("KILL", 9), ("USR1", 10), ("USR2", 12),
+    ("TERM", 15), ("CONT", 18), ("STOP", 19), ("TSTP", 20),
+];
+
+/// Windows can only terminate a process, whatever the signal.
+#[cfg(windows)]
+pub const SIGNALS: &[(&str, i32)] = &[("INT", 2), ("KILL", 9), ("TERM", 15)];
+
+/// Number of a signal given as `9`, `KILL` or `SIGKILL`.
+pub fn signal_number(name: &str) -> Option<i32> {
+    if let Ok(n) = name.parse() { return Some(n); }
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS.iter().find(|(n, _)| *n == name).map(|&(_, n)| n)
+}
+
+pub fn signal_name(sig: i32) -> Option<&'static str> {
+    SIGNALS.iter().find(|&&(_, n)| n == sig).map(|&(n, _)| n)
+}
+
+#[cfg(unix)]
+mod sys {
+    unsafe extern "C" {
+        pub fn kill(pid: i32, sig: i32) -> i32;
+        pub fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+        pub fn tcsetpgrp(fd: i32, pgrp: i32) -> i32;
+        pub fn tcgetpgrp(fd: i32) -> i32;
+        pub fn getpgrp() -> i32;
+        pub fn signal(sig: i32, handler: usize) -> usize;
+    }
+    pub const WNOHANG: i32 = 1;
+    pub const WUNTRACED: i32 = 2;
+    pub const SIGINT: i32 = 2;
+    pub const SIGTERM: i32 = 15;
+    #[cfg(target_os = "macos")]
+    pub const SIGTSTP: i32 = 18;
+    #[cfg(not(target_os = "macos"))]
+    pub const SIGTSTP: i32 = 20;
+    pub const SIGTTIN: i32 = 21;
+    pub const SIGTTOU: i32 = 22;
+    pub const SIG_DFL: usize = 0;
+    pub const SIG_IGN: usize = 1;
+}
+
+/// Put a command in its own process group (`pgid` 0 starts a new one
+/// led by the child), so terminal signals only reach it when it is
+/// given the terminal. The job-control signals an interactive shell
+/// ignores get their default action back. With `foreground` the child
+/// also takes the terminal itself, so it cannot try to read it before
+/// the shell has handed it over.
+#[cfg(unix)]
+pub fn set_process_group(cmd: &mut Command, pgid: u32, foreground: bool) {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(pgid as i32);
+    // SAFETY: runs in the forked child before `exec` and only makes
+    // async-signal-safe system calls.
+    unsafe {
+        cmd.pre_exec(move || {
+            if foreground {
+                sys::signal(sys::SIGTTOU, sys::SIG_IGN);
+                sys::tcsetpgrp(0, sys::getpgrp());
+            }
+            for sig in [sys::SIGTSTP, sys::SIGTTIN, sys::SIGTTOU] { sys::signal(sig, sys::SIG_DFL); }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(windows)]
+pub fn set_process_group(_cmd: &mut Command, _pgid: u32, _foreground: bool) {}
+
+/// Ignore Ctrl-Z and the signals for touching the terminal from the
+/// background, as an interactive shell does: only the commands it runs
+/// are stopped, never the shell.
+#[cfg(unix)]
+pub fn ignore_job_signals() {
+    // SAFETY: plain system calls.
+    unsafe {
+        for sig in [sys::SIGTSTP, sys::SIGTTIN, sys::SIGTTOU] { sys::signal(sig, sys::SIG_IGN); }
+    }
+}
+
+#[cfg(windows)]
+pub fn ignore_job_signals() {}
+
+/// Wait for a child to exit or stop; with `block` false, return `None`
+/// at once if it is still running.
+#[cfg(unix)]
+pub fn wait_child(child: &mut Child, block: bool) -> Option<Wait> {
+    let flags = sys::WUNTRACED | if block { 0 } else { sys::WNOHANG };
+    let mut status = 0;
+    loop {
+        // SAFETY: `status` is a valid out-pointer for the call.
+        let pid = unsafe { sys::waitpid(child.id() as i32, &mut status, flags) };
+        if pid == 0 { return None; }
+        if pid < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted { continue; }
+            return Some(Wait::Exited(1));
+        }
+        return Some(match status & 0x7f {
+            0 => Wait::Exited((status >> 8) & 0xff),
+            0x7f => Wait::Stopped,
+            sig => Wait::Exited(128 + sig),
+        });
+    }
+}
+
+#[cfg(windows)]
+pub fn wait_child(child: &mut Child, block: bool) -> Option<Wait> {
+    let status = if block { child.wait().ok() } else { child.try_wait().ok()? };
+    Some(Wait::Exited(status.map(exit_code).unwrap_or(1)))
+}
+
+/// Send `sig` to a process, or to a whole process group when `group`.
+#[cfg(unix)]
+pub fn send_signal(pid: u32, sig: i32, group: bool) -> std::io::Result<()> {
+    let target = if group { -(pid as i32) } else { pid as i32 };
+    // SAFETY: plain system call with integer arguments.
+    if unsafe { sys::kill(target, sig) } == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+}
+
+#[cfg(windows)]
+pub fn send_signal(pid: u32, _sig: i32, group: bool) -> std::io::Result<()> {
+    let mut c = Command::new("taskkill");
+    c.args(["/F", "/PID", &pid.to_string()]);
+    if group { c.arg("/T"); }
+    let status = c.stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).status()?;
+    if status.success() { Ok(()) } else { Err(std::io::Error::other("no such process")) }
+}
+
+/// Hand the terminal to process group `pgid`, or back to the shell with
+/// `None`. Does nothing when stdin is not a terminal.
+#[cfg(unix)]
+pub fn give_terminal(pgid: Option<u32>) {
+    use std::io::IsTerminal;
+    if !std::io::stdin().is_terminal() { return; }
+    // SAFETY: plain system calls; SIGTTOU is ignored around `tcsetpgrp`
+    // so the shell may call it while in the background.
+    unsafe {
+        let pgid = pgid.map_or_else(|| sys::getpgrp(), |p| p as i32);
+        let old = sys::signal(sys::SIGTTOU, sys::SIG_IGN);
+        sys::tcsetpgrp(0, pgid);
+        sys::signal(sys::SIGTTOU, old);
+    }
+}
+
+#[cfg(windows)]
+pub fn give_terminal(_pgid: Option<u32>) {}
+
+// ── Local time ───────────────────────────────────────────────
+
+#[cfg(unix)]
+mod clock {
+    use std::ffi::{c_char, c_int, c_long};
+
+    /// `struct tm`, with the `tm_gmtoff` and `tm_zone` fields that Linux,
+    /// the BSDs and macOS all have.
+    #[repr(C)]
+    pub struct Tm {
+        pub fields: [c_int; 9],
+        pub gmtoff: c_long,
+        pub zone: *const c_char,
+    }
+    unsafe extern "C" {
+        pub fn localtime_r(time: *const c_long, tm: *mut Tm) -> *mut Tm;
+    }
+}
+
+/// Seconds east of UTC of the local time zone at `secs` after the epoch.
+#[cfg(unix)]
+pub fn utc_offset(secs: u64) -> i64 {
+    let time = secs as std::ffi::c_long;
+    let mut tm = clock::Tm { fields: [0; 9], gmtoff: 0, zone: std::ptr::null() };
+    // SAFETY: `localtime_r` fills `tm` and keeps no pointer to either argument.
+    if unsafe { clock::localtime_r(&time, &mut tm) }.is_null() { return 0; }
+    tm.gmtoff as i64
+}
+
+#[cfg(windows)]
+mod clock {
+    #[repr(C)]
+    pub struct TimeZoneInformation {
+        pub bias: i32,
+        pub standard_name: [u16; 32],
+        pub standard_date: [u16; 8],
+        pub standard_bias: i32,
+        pub daylight_name: [u16; 32],
+        pub daylight_date: [u16; 8],
+        pub daylight_bias: i32,
+    }
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        pub fn GetTimeZoneInformation(info: *mut TimeZoneInformation) -> u32;
+    }
+    pub const TIME_ZONE_ID_DAYLIGHT: u32 = 2;
+    pub const TIME_ZONE_ID_INVALID: u32 = u32::MAX;
+}
+
+/// Seconds east of UTC of the local time zone. Windows only reports the
+/// current offset, so `secs` is not used.
+#[cfg(windows)]
+pub fn utc_offset(_secs: u64) -> i64 {
+    let mut info = clock::TimeZoneInformation {
+        bias: 0, standard_name: [0; 32], standard_date: [0; 8], standard_bias: 0,
+        daylight_name: [0; 32], daylight_date: [0; 8], daylight_bias: 0,
+    };
+    // SAFETY: the call fills `info`.
+    let zone = unsafe { clock::GetTimeZoneInformation(&mut info) };
+    if zone == clock::TIME_ZONE_ID_INVALID { return 0; }
+    let extra = if zone == clock::TIME_ZONE_ID_DAYLIGHT { info.daylight_bias } else { info.standard_bias };
+    -(info.bias as i64 + extra as i64) * 60
+}
+
+// ── Ctrl-C and termination ───────────────────────────────────
+
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+/// Foreground child that SIGINT has to be passed on to, or 0.
+static FOREGROUND: AtomicU32 = AtomicU32::new(0);
+
+/// Whether Ctrl-C was pressed since the last `take_interrupt`.
+pub fn interrupt_pending() -> bool { INTERRUPT.load(Ordering::SeqCst) }
+pub fn take_interrupt() -> bool { INTERRUPT.swap(false, Ordering::SeqCst) }
+pub fn take_terminate() -> bool { TERMINATE.swap(false, Ordering::SeqCst) }
+/// Act as if Ctrl-C reached the shell: used when a foreground command
+/// holding the terminal was killed by it.
+pub fn raise_interrupt() { INTERRUPT.store(true, Ordering::SeqCst); }
+
+#[cfg(unix)]
+extern "C" fn on_signal(sig: i32) {
+    if sig == sys::SIGINT {
+        INTERRUPT.store(true, Ordering::SeqCst);
+        let pid = FOREGROUND.load(Ordering::SeqCst);
+        // SAFETY: `kill` is async-signal-safe.
+        if pid != 0 { unsafe { sys::kill(pid as i32, sys::SIGINT); } }
+    } else {
+        TERMINATE.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Catch Ctrl-C so it sets a flag instead of killing the shell. Child
+/// processes get the default behaviour back when they start.
+#[cfg(unix)]
+pub fn catch_interrupt() {
+    // SAFETY: installs a handler that only touches atomics and calls `kill`.
+    unsafe { sys::signal(sys::SIGINT, on_signal as extern "C" fn(i32) as usize); }
+}
+
+/// Catch SIGTERM (for `trap ... TERM`), or restore the default.
+#[cfg(unix)]
+pub fn catch_terminate(on: bool) {
+    let handler = if on { on_signal as extern "C" fn(i32) as usize } else { sys::SIG_DFL };
+    // SAFETY: as in `catch_interrupt`.
+    unsafe { sys::signal(sys::SIGTERM, handler); }
+}
+
+/// Note the foreground child being waited for (0 when none). A child in
+/// the terminal's foreground process group gets Ctrl-C from the terminal
+/// itself; otherwise (e.g. `kill -INT` sent to the shell) it is passed on.
+#[cfg(unix)]
+pub fn set_foreground(pid: u32) {
+    use std::io::IsTerminal;
+    // SAFETY: plain system calls.
+    let from_terminal = std::io::stdin().is_terminal() && unsafe { sys::tcgetpgrp(0) == sys::getpgrp() };
+    FOREGROUND.store(if from_terminal { 0 } else { pid }, Ordering::SeqCst);
+}
+
+#[cfg(windows)]
+mod console {
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        pub fn SetConsoleCtrlHandler(handler: Option<unsafe extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+    pub const CTRL_C_EVENT: u32 = 0;
+    pub const CTRL_BREAK_EVENT: u32 = 1;
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn on_console_event(event: u32) -> i32 {
+    match event {
+        console::CTRL_C_EVENT | console::CTRL_BREAK_EVENT => { INTERRUPT.store(true, Ordering::SeqCst); 1 }
+        _ => { TERMINATE.store(true, Ordering::SeqCst); 0 }
+    }
+}
+
+/// Catch Ctrl-C so it sets a flag instead of killing the shell. The
+/// console delivers it to child processes as well.
+#[cfg(windows)]
+pub fn catch_interrupt() {
+    // SAFETY: registers a handler that only touches atomics.
+    unsafe { console::SetConsoleCtrlHandler(Some(on_console_event), 1); }
+}
+
+#[cfg(windows)]
+pub fn catch_terminate(_on: bool) {}
+
+#[cfg(windows)]
+pub fn set_foreground(pid: u32) { FOREGROUND.store(pid, Ordering::SeqCst); }
+
+// ── Terminal ─────────────────────────────────────────────────
+
+#[cfg(unix)]
+mod tty {
+    #[cfg(not(target_os = "macos"))]
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub iflag: u32, pub oflag: u32, pub cflag: u32, pub lflag: u32,
+        pub line: u8, pub cc: [u8; 32], pub ispeed: u32, pub ospeed: u32,
+    }
+    #[cfg(not(target_os = "macos"))]
+    mod flags {
+        pub const ICRNL: u32 = 0o400;
+        pub const IXON: u32 = 0o2000;
+        pub const ISIG: u32 = 0o1;
+        pub const ICANON: u32 = 0o2;
+        pub const ECHO: u32 = 0o10;
+        pub const IEXTEN: u32 = 0o100000;
+        pub const VTIME: usize = 5;
+        pub const VMIN: usize = 6;
+        pub const TIOCGWINSZ: std::ffi::c_ulong = 0x5413;
+    }
+
+    #[cfg(target_os = "macos")]
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub iflag: u64, pub oflag: u64, pub cflag: u64, pub lflag: u64,
+        pub cc: [u8; 20], pub ispeed: u64, pub ospeed: u64,
+    }
+    #[cfg(target_os = "macos")]
+    mod flags {
+        pub const ICRNL: u64 = 0x100;
+        pub const IXON: u64 = 0x200;
+        pub const ISIG: u64 = 0x80;
+        pub const ICANON: u64 = 0x100;
+        pub const ECHO: u64 = 0x8;
+        pub const IEXTEN: u64 = 0x400;
+        pub const VMIN: usize = 16;
+        pub const VTIME: usize = 17;
+        pub const TIOCGWINSZ: std::ffi::c_ulong = 0x40087468;
+    }
+    pub use flags::*;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Winsize { pub rows: u16, pub cols: u16, pub xpixel: u16, pub ypixel: u16 }
+
+    pub const TCSANOW: i32 = 0;
+
+    unsafe extern "C" {
+        pub fn tcgetattr(fd: i32, t: *mut Termios) -> i32;
+        pub fn tcsetattr(fd: i32, action: i32, t: *const Termios) -> i32;
+        pub fn ioctl(fd: i32, request: std::ffi::c_ulong, ...) -> i32;
+    }
+}
+
+/// Keeps the terminal in raw mode (keys arrive one at a time, unechoed,
+/// Ctrl-C as a byte) until dropped.
+#[cfg(unix)]
+pub struct RawMode(tty::Termios);
+
+/// Switch stdin to raw mode for the line editor; `None` if it is not a
+/// terminal. Output processing stays on, so `\n` still starts a new line.
+#[cfg(unix)]
+pub fn raw_mode() -> Option<RawMode> {
+    use std::mem::MaybeUninit;
+    let mut t = MaybeUninit::<tty::Termios>::uninit();
+    // SAFETY: `tcgetattr` fills the struct when it returns 0.
+    let saved = unsafe {
+        if tty::tcgetattr(0, t.as_mut_ptr()) != 0 { return None; }
+        t.assume_init()
+    };
+    let mut raw = saved;
+    raw.iflag &= !(tty::ICRNL | tty::IXON);
+    raw.lflag &= !(tty::ICANON | tty::ECHO | tty::ISIG | tty::IEXTEN);
+    raw.cc[tty::VMIN] = 1;
+    raw.cc[tty::VTIME] = 0;
+    // SAFETY: `raw` is a valid termios copied from the terminal.
+    if unsafe { tty::tcsetattr(0, tty::TCSANOW, &raw) } != 0 { return None; }
+    Some(RawMode(saved))
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in `raw_mode`.
+        unsafe { tty::tcsetattr(0, tty::TCSANOW, &self.0); }
+    }
+}
+
+/// Columns of the terminal on stdout, or `COLUMNS`, or 80.
+#[cfg(unix)]
+pub fn terminal_width() -> usize {
+    let mut ws = tty::Winsize::default();
+    // SAFETY: TIOCGWINSZ writes a `Winsize` through the pointer.
+    if unsafe { tty::ioctl(1, tty::TIOCGWINSZ, &mut ws as *mut tty::Winsize) } == 0 && ws.cols > 0 {
+        return ws.cols as usize;
+    }
+    env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).filter(|&c| c > 0).unwrap_or(80)
+}
+
+#[cfg(windows)]
+mod tty {
+    pub type Handle = *mut std::ffi::c_void;
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct ScreenBufferInfo {
+        pub size: [i16; 2], pub cursor: [i16; 2], pub attributes: u16,
+        pub window: [i16; 4], pub max_size: [i16; 2],
+    }
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        pub fn GetStdHandle(which: u32) -> Handle;
+        pub fn GetConsoleMode(handle: Handle, mode: *mut u32) -> i32;
+        pub fn SetConsoleMode(handle: Handle, mode: u32) -> i32;
+        pub fn GetConsoleScreenBufferInfo(handle: Handle, info: *mut ScreenBufferInfo) -> i32;
+    }
+    pub const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+    pub const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    pub const ENABLE_PROCESSED_INPUT: u32 = 0x1;
+    pub const ENABLE_LINE_INPUT: u32 = 0x2;
+    pub const ENABLE_ECHO_INPUT: u32 = 0x4;
+    pub const ENABLE_VIRTUAL_TERMINAL_INPUT: u32 = 0x200;
+}
+
+/// Keeps the console in raw mode (keys arrive one at a time as VT
+/// sequences, unechoed, Ctrl-C as a byte) until dropped.
+#[cfg(windows)]
+pub struct RawMode(u32);
+
+#[cfg(windows)]
+pub fn raw_mode() -> Option<RawMode> {
+    let mut saved = 0;
+    // SAFETY: console calls on the process's own stdin handle.
+    unsafe {
+        let input = tty::GetStdHandle(tty::STD_INPUT_HANDLE);
+        if tty::GetConsoleMode(input, &mut saved) == 0 { return None; }
+        let raw = (saved & !(tty::ENABLE_PROCESSED_INPUT | tty::ENABLE_LINE_INPUT | tty::ENABLE_ECHO_INPUT))
+            | tty::ENABLE_VIRTUAL_TERMINAL_INPUT;
+        if tty::SetConsoleMode(input, raw) == 0 { return None; }
+    }
+    Some(RawMode(saved))
+}
+
+#[cfg(windows)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the mode read in `raw_mode`.
+        unsafe { tty::SetConsoleMode(tty::GetStdHandle(tty::STD_INPUT_HANDLE), self.0); }
+    }
+}
+
+#[cfg(windows)]
+pub fn terminal_width() -> usize {
+    let mut info = tty::ScreenBufferInfo::default();
+    // SAFETY: the call fills `info` when it returns non-zero.
+    if unsafe { tty::GetConsoleScreenBufferInfo(tty::GetStdHandle(tty::STD_OUTPUT_HANDLE), &mut info) } != 0 {
+        let cols = info.window[2] - info.window[0] + 1;
+        if cols > 0 { return cols as usize; }
+    }
+    env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).filter(|&c| c > 0).unwrap_or(80)
+}
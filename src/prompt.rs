@@ -0,0 +1,151 @@
+// ══════════════════════════════════════════════════════════════════
+// Prompt — the interactive prompt, `PROMPT` escapes and the hook
+// ══════════════════════════════════════════════════════════════════
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{dirs, local_datetime, platform, unix_secs, Shell};
+
+/// Branch checked out in the repository containing `dir`, read from
+/// `.git/HEAD`; a detached HEAD shows its short commit hash.
+fn git_branch(dir: &Path) -> Option<String> {
+    let dot_git = dir.ancestors().map(|d| d.join(".git")).find(|g| g.exists())?;
+    // Worktrees and submodules have a `.git` file pointing elsewhere.
+    let git_dir = if dot_git.is_file() {
+        let link = fs::read_to_string(&dot_git).ok()?;
+        let target = PathBuf::from(link.strip_prefix("gitdir:")?.trim());
+        if target.is_absolute() { target } else { dot_git.parent()?.join(target) }
+    } else {
+        dot_git
+    };
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    Some(match head.strip_prefix("ref: ") {
+        Some(r) => r.strip_prefix("refs/heads/").unwrap_or(r).to_string(),
+        None => head.chars().take(7).collect(),
+    })
+}
+
+/// `350ms`, `4.2s`, `3m07s`.
+fn format_duration(d: Duration) -> String {
+    let ms = d.as_millis();
+    if ms < 1000 { format!("{}ms", ms) }
+    else if ms < 60_000 { format!("{:.1}s", d.as_secs_f64()) }
+    else { format!("{}m{:02}s", ms / 60_000, ms / 1000 % 60) }
+}
+
+impl Shell {
+    /// Print the interactive prompt: the output of a `prompt` function if
+    /// one is defined, else `$PROMPT`, else the cwd and `nes>`.
+    pub fn prompt(&mut self, out: &mut impl Write) {
+        let template = if self.functions.contains_key("prompt") {
+            let status = self.status;
+            let text = self.command_output("prompt");
+            self.status = status;
+            Some(text)
+        } else {
+            self.vars.get("PROMPT").cloned().or_else(|| env::var("PROMPT").ok())
+        };
+        match template {
+            Some(t) => {
+                let cwd = env::current_dir().unwrap_or_default();
+                let _ = out.write_all(self.expand_prompt(&t, &cwd).as_bytes());
+            }
+            None => {
+                let cwd = env::current_dir()
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .unwrap_or_else(|_| "?".into());
+                let _ = write!(out, "\x1b[36m{}\x1b[0m \x1b[33mnes>\x1b[0m ", cwd);
+            }
+        }
+        let _ = out.flush();
+    }
+
+    /// Replace the prompt escapes in `template`:
+    /// `\w` cwd with `~` for home, `\W` last part of the cwd, `\u` user,
+    /// `\h` host, `\t` time, `\?` last status, `\d` duration of the last
+    /// command, `\g` git branch, `\n` newline, `\e` ESC (for colours), `\\`.
+    fn expand_prompt(&self, template: &str, cwd: &Path) -> String {
+        let mut s = String::with_capacity(template.len() + 32);
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' { s.push(c); continue; }
+            match chars.next() {
+                Some('w') => s.push_str(&dirs::tilde_path(cwd)),
+                Some('W') => match cwd.file_name() {
+                    _ if dirs::tilde_path(cwd) == "~" => s.push('~'),
+                    Some(name) => s.push_str(&name.to_string_lossy()),
+                    None => s.push_str(&cwd.to_string_lossy()),
+                },
+                Some('u') => s.push_str(&platform::user_name()),
+                Some('h') => s.push_str(&platform::host_name()),
+                Some('t') => {
+                    let (_, _, _, h, mi, sec) = local_datetime(unix_secs());
+                    s.push_str(&format!("{:02}:{:02}:{:02}", h, mi, sec));
+                }
+                Some('?') => s.push_str(&self.status.to_string()),
+                Some('d') => s.push_str(&format_duration(self.last_duration)),
+                Some('g') => s.push_str(&git_branch(cwd).unwrap_or_default()),
+                Some('n') => s.push('\n'),
+                Some('e') => s.push('\x1b'),
+                Some('\\') => s.push('\\'),
+                Some(other) => { s.push('\\'); s.push(other); }
+                None => s.push('\\'),
+            }
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes() {
+        let mut sh = Shell::new();
+        sh.status = 3;
+        sh.last_duration = Duration::from_millis(4200);
+        let dir = Path::new("/usr/lib");
+        assert_eq!(sh.expand_prompt("\\W:\\? \\d\\n\\e[0m\\\\ \\x\\", dir), "lib:3 4.2s\n\x1b[0m\\ \\x\\");
+        assert_eq!(sh.expand_prompt("\\w", dir), "/usr/lib");
+        assert_eq!(sh.expand_prompt("\\W", Path::new("/")), "/");
+        if let Some(home) = platform::home_dir() {
+            let home = PathBuf::from(home);
+            assert_eq!(sh.expand_prompt("\\w \\W", &home), "~ ~");
+            assert_eq!(sh.expand_prompt("\\W", &home.join("src")), "src");
+        }
+        let time = sh.expand_prompt("\\t", dir);
+        assert!(time.len() == 8 && time.chars().enumerate().all(|(i, c)| if i % 3 == 2 { c == ':' } else { c.is_ascii_digit() }), "{}", time);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::from_millis(350)), "350ms");
+        assert_eq!(format_duration(Duration::from_millis(4200)), "4.2s");
+        assert_eq!(format_duration(Duration::from_secs(187)), "3m07s");
+    }
+
+    #[test]
+    fn git_branches() {
+        let root = env::temp_dir().join(format!("nes-git-{}", std::process::id()));
+        let (repo, linked) = (root.join("repo"), root.join("linked"));
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(repo.join("src/deep")).unwrap();
+        fs::create_dir_all(root.join("store/worktrees/linked")).unwrap();
+        fs::create_dir_all(&linked).unwrap();
+        fs::write(repo.join(".git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        assert_eq!(git_branch(&repo.join("src/deep")).as_deref(), Some("feature/x"));
+        fs::write(repo.join(".git/HEAD"), "0123456789abcdef0123456789abcdef01234567\n").unwrap();
+        assert_eq!(git_branch(&repo).as_deref(), Some("0123456"));
+        fs::write(linked.join(".git"), "gitdir: ../store/worktrees/linked\n").unwrap();
+        fs::write(root.join("store/worktrees/linked/HEAD"), "ref: refs/heads/main\n").unwrap();
+        assert_eq!(git_branch(&linked).as_deref(), Some("main"));
+        assert_eq!(git_branch(&root), None);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
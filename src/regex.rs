@@ -0,0 +1,391 @@
+// ══════════════════════════════════════════════════════════════════
+// Regex — a small Thompson-NFA matcher for `=~` conditions
+// ══════════════════════════════════════════════════════════════════
+//
+// Supports literals, `.`, `^`, `$`, `[...]` / `[^...]` with ranges,
+// `\d \w \s` (and `\D \W \S`), groups with `|`, and the quantifiers
+// `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}`.
+//
+// The pattern is compiled to a small program and run over the text one
+// character at a time, keeping every live thread at once (Pike's VM).
+// Matching takes time linear in the text and never recurses on it, so
+// long inputs cannot overflow the stack.
+
+enum Node {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negate: bool },
+    Start,
+    End,
+    /// `(a|b)`: alternatives, each a sequence.
+    Group(Vec<Vec<Node>>),
+    Repeat { node: Box<Node>, min: usize, max: Option<usize> },
+}
+
+/// An instruction of the compiled program.
+enum Inst {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negate: bool },
+    Start,
+    End,
+    /// Continue at both targets.
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+pub struct Regex {
+    prog: Vec<Inst>,
+}
+
+/// Most instructions a pattern may compile to; `{n,m}` copies its atom,
+/// so nested counts could otherwise grow without bound.
+const MAX_PROGRAM: usize = 100_000;
+/// Deepest nesting of groups the parser accepts.
+const MAX_DEPTH: usize = 200;
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut p = Parser { chars: pattern.chars().collect(), pos: 0, depth: 0 };
+        let alts = p.alternation()?;
+        if p.pos < p.chars.len() { return Err(p.err("unmatched ')'")); }
+        let mut prog = Vec::new();
+        compile_alts(&mut prog, &alts)?;
+        prog.push(Inst::Match);
+        Ok(Regex { prog })
+    }
+
+    /// Whether the pattern matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        // `seen[pc]` is the position at which `pc` was last added, so each
+        // instruction runs at most once per position.
+        let mut seen = vec![usize::MAX; self.prog.len()];
+        let (mut current, mut next) = (Vec::new(), Vec::new());
+        for i in 0..=text.len() {
+            // A new thread starts at every position: the match is unanchored.
+            if self.add_thread(&mut current, &mut seen, 0, i, text.len()) { return true; }
+            let Some(&c) = text.get(i) else { break };
+            for &pc in &current {
+                let step = match &self.prog[pc] {
+                    Inst::Char(x) => *x == c,
+                    Inst::Any => c != '\n',
+                    Inst::Class { ranges, negate } => ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negate,
+                    _ => false,
+                };
+                if step && self.add_thread(&mut next, &mut seen, pc + 1, i + 1, text.len()) { return true; }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        false
+    }
+
+    /// Follow jumps, splits and anchors from `pc` at position `i`, adding
+    /// the instructions that consume a character to `list`. Returns true
+    /// when `Match` is reachable.
+    fn add_thread(&self, list: &mut Vec<usize>, seen: &mut [usize], pc: usize, i: usize, len: usize) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if seen[pc] == i { continue; }
+            seen[pc] = i;
+            match self.prog[pc] {
+                Inst::Match => return true,
+                Inst::Jmp(to) => stack.push(to),
+                Inst::Split(a, b) => { stack.push(b); stack.push(a); }
+                Inst::Start => if i == 0 { stack.push(pc + 1); },
+                Inst::End => if i == len { stack.push(pc + 1); },
+                _ => list.push(pc),
+            }
+        }
+        false
+    }
+}
+
+// ── Parser ────────────────────────────────────────────────────
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// Groups currently open.
+    depth: usize,
+}
+
+impl Parser {
+    fn err(&self, msg: &str) -> String { format!("invalid regex: {}", msg) }
+    fn peek(&self) -> Option<char> { self.chars.get(self.pos).copied() }
+
+    fn alternation(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alts = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alts.push(self.sequence()?);
+        }
+        Ok(alts)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut seq = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' { break; }
+            let atom = self.atom()?;
+            seq.push(self.quantified(atom)?);
+        }
+        Ok(seq)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.chars[self.pos];
+        self.pos += 1;
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '*' | '+' | '?' => return Err(self.err(&format!("nothing to repeat before '{}'", c))),
+            '(' => {
+                if self.chars[self.pos..].starts_with(&['?', ':']) { self.pos += 2; }
+                if self.depth == MAX_DEPTH { return Err(self.err("groups nested too deeply")); }
+                self.depth += 1;
+                let alts = self.alternation()?;
+                self.depth -= 1;
+                if self.peek() != Some(')') { return Err(self.err("missing ')'")); }
+                self.pos += 1;
+                Node::Group(alts)
+            }
+            '[' => self.class()?,
+            '\\' => {
+                let e = self.peek().ok_or_else(|| self.err("trailing '\\'"))?;
+                self.pos += 1;
+                escape_node(e)
+            }
+            c => Node::Char(c),
+        })
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negate = self.peek() == Some('^');
+        if negate { self.pos += 1; }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or_else(|| self.err("missing ']'"))?;
+            self.pos += 1;
+            if c == ']' && !first { break; }
+            first = false;
+            let lo = if c == '\\' {
+                let e = self.peek().ok_or_else(|| self.err("missing ']'"))?;
+                self.pos += 1;
+                match e {
+                    'd' => { ranges.extend_from_slice(DIGIT); continue; }
+                    'w' => { ranges.extend_from_slice(WORD); continue; }
+                    's' => { ranges.extend_from_slice(SPACE); continue; }
+                    e => escaped_char(e),
+                }
+            } else { c };
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                let hi = self.chars[self.pos + 1];
+                self.pos += 2;
+                if hi < lo { return Err(self.err(&format!("bad range {}-{}", lo, hi))); }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+        Ok(Node::Class { ranges, negate })
+    }
+
+    fn quantified(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => { self.pos += 1; (0, None) }
+            Some('+') => { self.pos += 1; (1, None) }
+            Some('?') => { self.pos += 1; (0, Some(1)) }
+            Some('{') => match self.braces() {
+                Some(bounds) => bounds,
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        if matches!(atom, Node::Start | Node::End) { return Err(self.err("nothing to repeat")); }
+        if max.is_some_and(|m| m < min) { return Err(self.err(&format!("bad repeat {{{},{}}}", min, max.unwrap_or(0)))); }
+        let node = Node::Repeat { node: Box::new(atom), min, max };
+        match self.peek() {
+            Some('*' | '+' | '?') => Err(self.err("repeated quantifier")),
+            _ => Ok(node),
+        }
+    }
+
+    /// `{n}`, `{n,}` or `{n,m}`; consumes it and returns the bounds, or
+    /// leaves `pos` alone when the brace is just a literal.
+    fn braces(&mut self) -> Option<(usize, Option<usize>)> {
+        let end = (self.pos..self.chars.len()).find(|&i| self.chars[i] == '}')?;
+        let body: String = self.chars[self.pos + 1..end].iter().collect();
+        let bounds = match body.split_once(',') {
+            None => { let n = body.parse().ok()?; (n, Some(n)) }
+            Some((lo, "")) => (lo.parse().ok()?, None),
+            Some((lo, hi)) => (lo.parse().ok()?, Some(hi.parse().ok()?)),
+        };
+        self.pos = end + 1;
+        Some(bounds)
+    }
+}
+
+fn escaped_char(e: char) -> char {
+    match e {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        e => e,
+    }
+}
+
+fn escape_node(e: char) -> Node {
+    let class = |ranges: &[(char, char)], negate| Node::Class { ranges: ranges.to_vec(), negate };
+    match e {
+        'd' => class(DIGIT, false),
+        'D' => class(DIGIT, true),
+        'w' => class(WORD, false),
+        'W' => class(WORD, true),
+        's' => class(SPACE, false),
+        'S' => class(SPACE, true),
+        e => Node::Char(escaped_char(e)),
+    }
+}
+
+// ── Compiler ──────────────────────────────────────────────────
+
+/// `a|b|c` as splits to each alternative, every one but the last
+/// jumping past the others when it is done.
+fn compile_alts(prog: &mut Vec<Inst>, alts: &[Vec<Node>]) -> Result<(), String> {
+    let mut jumps = Vec::new();
+    for (n, alt) in alts.iter().enumerate() {
+        if n + 1 == alts.len() { compile_seq(prog, alt)?; break; }
+        let split = prog.len();
+        prog.push(Inst::Split(split + 1, 0));
+        compile_seq(prog, alt)?;
+        jumps.push(prog.len());
+        prog.push(Inst::Jmp(0));
+        prog[split] = Inst::Split(split + 1, prog.len());
+    }
+    let end = prog.len();
+    for j in jumps { prog[j] = Inst::Jmp(end); }
+    Ok(())
+}
+
+fn compile_seq(prog: &mut Vec<Inst>, seq: &[Node]) -> Result<(), String> {
+    seq.iter().try_for_each(|node| compile(prog, node))
+}
+
+fn compile(prog: &mut Vec<Inst>, node: &Node) -> Result<(), String> {
+    if prog.len() > MAX_PROGRAM { return Err("invalid regex: pattern too large".into()); }
+    match node {
+        Node::Char(c) => prog.push(Inst::Char(*c)),
+        Node::Any => prog.push(Inst::Any),
+        Node::Class { ranges, negate } => prog.push(Inst::Class { ranges: ranges.clone(), negate: *negate }),
+        Node::Start => prog.push(Inst::Start),
+        Node::End => prog.push(Inst::End),
+        Node::Group(alts) => compile_alts(prog, alts)?,
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min { compile(prog, node)?; }
+            match max {
+                // A loop: split into another copy or out.
+                None => {
+                    let split = prog.len();
+                    prog.push(Inst::Split(split + 1, 0));
+                    compile(prog, node)?;
+                    prog.push(Inst::Jmp(split));
+                    prog[split] = Inst::Split(split + 1, prog.len());
+                }
+                // Each optional copy may skip to the end.
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(prog.len());
+                        prog.push(Inst::Split(0, 0));
+                        compile(prog, node)?;
+                    }
+                    let end = prog.len();
+                    for s in splits { prog[s] = Inst::Split(s + 1, end); }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(is_match("^ab", "abc"));
+        assert!(!is_match("^b", "abc"));
+        assert!(is_match("bc$", "abc"));
+        assert!(!is_match("b$", "abc"));
+        assert!(is_match("^$", ""));
+        assert!(is_match("b", "abc"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(is_match("^[a-c]+$", "abcba"));
+        assert!(!is_match("^[a-c]+$", "abd"));
+        assert!(is_match("^[^0-9]+$", "abc"));
+        assert!(is_match("^v\\d+\\.\\d+", "v1.20"));
+        assert!(is_match("^\\w+\\s\\S$", "foo_1 x"));
+        assert!(is_match("[]x]", "]"));
+        assert!(is_match("^a.c$", "abc"));
+        assert!(!is_match("^a.c$", "a\nc"));
+    }
+
+    #[test]
+    fn quantifiers() {
+        assert!(is_match("^ab*c$", "ac"));
+        assert!(is_match("^ab+c$", "abbbc"));
+        assert!(!is_match("^ab+c$", "ac"));
+        assert!(is_match("^ab?c$", "abc"));
+        assert!(!is_match("^ab?c$", "abbc"));
+        assert!(is_match("^a{3}$", "aaa"));
+        assert!(!is_match("^a{3}$", "aa"));
+        assert!(is_match("^a{2,}$", "aaaa"));
+        assert!(is_match("^a{1,2}$", "aa"));
+        assert!(!is_match("^a{1,2}$", "aaa"));
+        assert!(is_match("^a{x}$", "a{x}"));
+        assert!(is_match("^(a*)*$", "aaa"));
+    }
+
+    #[test]
+    fn alternation() {
+        assert!(is_match("^(cat|dog)s?$", "dogs"));
+        assert!(!is_match("^(cat|dog)$", "cow"));
+        assert!(is_match("^(?:a|b)+c$", "abbac"));
+        assert!(is_match("x|^y", "y"));
+    }
+
+    #[test]
+    fn errors() {
+        for bad in ["(a", "a)", "*a", "[ab", "a**", "[z-a]", "a{3,1}", "\\"] {
+            assert!(Regex::new(bad).is_err(), "{}", bad);
+        }
+        assert!(Regex::new(&"(".repeat(1000)).is_err());
+        assert!(Regex::new("(a{1000}){1000}").is_err());
+    }
+
+    #[test]
+    fn long_input() {
+        let text = format!("{}b", "a".repeat(200_000));
+        assert!(is_match("^a*b", &text));
+        assert!(is_match("^(a|b)*$", &text));
+        assert!(!is_match("^a*c", &text));
+        assert!(!is_match("(a*)*c", &text));
+    }
+}
@@ -0,0 +1,105 @@
+// ══════════════════════════════════════════════════════════════════
+// Signals — Ctrl-C, SIGTERM and `trap` handlers
+// ══════════════════════════════════════════════════════════════════
+//
+// The OS handlers in `platform` only raise flags. The shell looks at
+// them between commands and loop passes: a trap runs its handler, and
+// an untrapped Ctrl-C abandons whatever is running with status 130.
+
+use std::sync::atomic::Ordering;
+
+use crate::pipeline::Sink;
+use crate::{platform, syntax, Shell};
+
+/// Conditions `trap` accepts, in the order `trap` lists them.
+const TRAP_NAMES: &[&str] = &["EXIT", "INT", "TERM"];
+
+/// `INT`, `SIGINT` or `2` → `INT`; `0` is `EXIT`.
+fn trap_name(spec: &str) -> Option<&'static str> {
+    let upper = spec.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    match name {
+        "0" | "EXIT" => Some("EXIT"),
+        "2" | "INT" => Some("INT"),
+        "15" | "TERM" => Some("TERM"),
+        _ => None,
+    }
+}
+
+impl Shell {
+    /// Act on Ctrl-C or SIGTERM received since the last check, and say
+    /// whether execution may go on.
+    pub fn check_signals(&mut self, out: &mut dyn Sink) -> bool {
+        // A pipeline stage on its own thread leaves signals to the main
+        // shell, and stops on Ctrl-C or once its reader is gone.
+        if let Some(closed) = &self.pipe_closed {
+            return self.running && !closed.load(Ordering::SeqCst) && !platform::interrupt_pending();
+        }
+        if platform::take_interrupt() {
+            match self.traps.get("INT").cloned() {
+                Some(handler) => self.run_trap(&handler, out),
+                None => { self.interrupted = true; self.status = 130; }
+            }
+        }
+        if platform::take_terminate() {
+            match self.traps.get("TERM").cloned() {
+                Some(handler) => self.run_trap(&handler, out),
+                None => {
+                    (self.status, self.running) = (143, false);
+                    self.run_exit_trap(out);
+                }
+            }
+        }
+        self.running && !self.interrupted
+    }
+
+    /// Run a trap handler, keeping `$?` unless the handler exits.
+    fn run_trap(&mut self, handler: &str, out: &mut dyn Sink) {
+        if handler.is_empty() { return; }
+        let status = self.status;
+        self.exec(handler, out);
+        if self.running { self.status = status; }
+    }
+
+    /// Run the `EXIT` trap once, as the shell finishes.
+    pub fn run_exit_trap(&mut self, out: &mut dyn Sink) {
+        let Some(handler) = self.traps.remove("EXIT") else { return };
+        let (status, running) = (self.status, self.running);
+        (self.running, self.interrupted) = (true, false);
+        self.run_trap(&handler, out);
+        if self.running { (self.status, self.running) = (status, running); }
+    }
+
+    /// `trap [handler | '' | -] SIGNAL...`: set, ignore or reset the
+    /// handler for `INT`, `TERM` or `EXIT`; with no arguments, list them.
+    pub fn builtin_trap(&mut self, args: &[String], out: &mut dyn Sink) -> i32 {
+        if args.is_empty() {
+            for name in TRAP_NAMES {
+                if let Some(h) = self.traps.get(*name) {
+                    let _ = out.write_all(format!("trap -- {} {}\n", syntax::quote(h), name).as_bytes());
+                }
+            }
+            return 0;
+        }
+        if args.len() < 2 {
+            let _ = out.write_all(b"Usage: trap <handler | '' | -> <INT|TERM|EXIT>...\n");
+            return 2;
+        }
+        let action = args[0].as_str();
+        let mut status = 0;
+        for spec in &args[1..] {
+            let Some(name) = trap_name(spec) else {
+                self.report(out, &format!("trap: {}: invalid signal specification", spec));
+                status = 1;
+                continue;
+            };
+            if action == "-" { self.traps.remove(name); } else { self.traps.insert(name.into(), action.into()); }
+            // SIGTERM is caught while an EXIT trap is set too, so that the
+            // trap still runs when it ends the shell.
+            if name != "INT" && self.pipe_closed.is_none() {
+                platform::catch_terminate(self.traps.contains_key("TERM") || self.traps.contains_key("EXIT"));
+            }
+        }
+        status
+    }
+}
@@ -0,0 +1,482 @@
+// ══════════════════════════════════════════════════════════════════
+// NesC syntax — lexer, parser and AST for shell command lines
+// ══════════════════════════════════════════════════════════════════
+
+use std::fmt;
+
+/// One piece of a shell word.
+#[derive(Clone, Debug)]
+pub enum WordPart {
+    /// Unquoted text.
+    Lit(String),
+    /// Text from inside quotes — never split or expanded further.
+    Quoted(String),
+    /// `$name`, `$1`, `$?`, `$@`, `$#` or `$!`; `quoted` when it appeared
+    /// inside double quotes.
+    Var { name: String, quoted: bool },
+    /// `${...}`: the text between the braces, evaluated at expansion time.
+    Param { body: String, quoted: bool },
+    /// `$(command)`, kept as source text and run at expansion time.
+    Subst { src: String, quoted: bool },
+}
+
+pub type Word = Vec<WordPart>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RedirOp {
+    /// `< file`
+    Read,
+    /// `> file` / `>> file`
+    Write, Append,
+    /// `2> file` / `2>> file`
+    ErrWrite, ErrAppend,
+    /// `2>&1`
+    ErrToOut,
+    /// `&> file` / `&>> file`
+    AllWrite, AllAppend,
+}
+
+#[derive(Clone, Debug)]
+pub struct Redirect {
+    pub op: RedirOp,
+    /// File name; empty for `2>&1`.
+    pub target: Word,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SimpleCommand {
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+pub type Pipeline = Vec<SimpleCommand>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Connector { Seq, And, Or }
+
+/// One pipeline of a list and how it is joined to the one before it.
+/// The first connector is always `Seq`.
+#[derive(Clone, Debug)]
+pub struct Item {
+    pub connector: Connector,
+    pub pipeline: Pipeline,
+    /// Ended by `&`: runs in the background.
+    pub background: bool,
+}
+
+/// Pipelines joined by `;`, `&`, `&&` and `||`.
+#[derive(Clone, Debug, Default)]
+pub struct List {
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.msg)
+    }
+}
+
+/// Quote `arg` so that it parses back as the same single word, with
+/// nothing in it expanded again: not variables, and not wildcards,
+/// braces or a leading `~` either.
+pub fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && !arg.chars().any(|c| c.is_whitespace() || "'\"$|&;<>*?[]{},\\".contains(c))
+        && !arg.starts_with(['~', '#'])
+        && !arg.starts_with("2>");
+    if plain { return arg.to_string(); }
+    // Single quotes keep everything literal; a `'` itself is written as
+    // `"'"` between two single-quoted runs.
+    let mut q = String::with_capacity(arg.len() + 2);
+    q.push('\'');
+    for c in arg.chars() {
+        if c == '\'' { q.push_str("'\"'\"'"); } else { q.push(c); }
+    }
+    q.push('\'');
+    q
+}
+
+/// Index of the `)` that closes a `$(` whose body starts at `start`,
+/// skipping quoted text and nested parentheses.
+pub fn subst_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0u32;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\'' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '\'' { i += 1; }
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' { i += 1; }
+                    i += 1;
+                }
+            }
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Index of the `}` that closes a `${` whose body starts at `start`,
+/// skipping quoted text and nested braces.
+pub fn brace_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0u32;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\'' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '\'' { i += 1; }
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' { i += 1; }
+                    i += 1;
+                }
+            }
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// ── Lexer ─────────────────────────────────────────────────────
+
+#[derive(Debug)]
+enum Tok {
+    Word(Word),
+    Pipe, AndAnd, OrOr, Semi, Amp,
+    Redir(RedirOp),
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+fn is_name_char(c: char) -> bool { c.is_alphanumeric() || c == '_' }
+fn is_var_start(c: char) -> bool { is_name_char(c) || matches!(c, '?' | '@' | '#' | '!') }
+
+impl Lexer {
+    fn err(&self, col: usize, msg: impl Into<String>) -> ParseError {
+        ParseError { line: self.line, col, msg: msg.into() }
+    }
+    fn peek(&self, off: usize) -> Option<char> { self.chars.get(self.pos + off).copied() }
+
+    fn tokens(mut self) -> Result<Vec<(usize, Tok)>, ParseError> {
+        let mut toks = Vec::new();
+        let mut word: Option<(usize, Word)> = None;
+        while let Some(c) = self.peek(0) {
+            let col = self.pos + 1;
+            let at_word_start = word.is_none();
+            let op = match c {
+                '2' if at_word_start && self.peek(1) == Some('>') => match (self.peek(2), self.peek(3)) {
+                    (Some('&'), Some('1')) => Some((Tok::Redir(RedirOp::ErrToOut), 4)),
+                    (Some('>'), _) => Some((Tok::Redir(RedirOp::ErrAppend), 3)),
+                    _ => Some((Tok::Redir(RedirOp::ErrWrite), 2)),
+                },
+                '&' if self.peek(1) == Some('>') => match self.peek(2) {
+                    Some('>') => Some((Tok::Redir(RedirOp::AllAppend), 3)),
+                    _ => Some((Tok::Redir(RedirOp::AllWrite), 2)),
+                },
+                '<' => Some((Tok::Redir(RedirOp::Read), 1)),
+                '|' if self.peek(1) == Some('|') => Some((Tok::OrOr, 2)),
+                '|' => Some((Tok::Pipe, 1)),
+                '&' if self.peek(1) == Some('&') => Some((Tok::AndAnd, 2)),
+                '&' => Some((Tok::Amp, 1)),
+                ';' => Some((Tok::Semi, 1)),
+                '>' if self.peek(1) == Some('>') => Some((Tok::Redir(RedirOp::Append), 2)),
+                '>' => Some((Tok::Redir(RedirOp::Write), 1)),
+                _ => None,
+            };
+            if let Some((tok, len)) = op {
+                if let Some((wc, w)) = word.take() { toks.push((wc, Tok::Word(w))); }
+                toks.push((col, tok));
+                self.pos += len;
+                continue;
+            }
+            if c.is_whitespace() {
+                if let Some((wc, w)) = word.take() { toks.push((wc, Tok::Word(w))); }
+                self.pos += 1;
+                continue;
+            }
+            let parts = &mut word.get_or_insert_with(|| (col, Vec::new())).1;
+            match c {
+                '\'' => {
+                    self.pos += 1;
+                    let start = self.pos;
+                    while self.peek(0).is_some_and(|c| c != '\'') { self.pos += 1; }
+                    if self.peek(0).is_none() { return Err(self.err(col, "unterminated single quote")); }
+                    parts.push(WordPart::Quoted(self.chars[start..self.pos].iter().collect()));
+                    self.pos += 1;
+                }
+                '"' => {
+                    self.pos += 1;
+                    let first = parts.len();
+                    let mut text = String::new();
+                    loop {
+                        match self.peek(0) {
+                            None => return Err(self.err(col, "unterminated double quote")),
+                            Some('"') => { self.pos += 1; break; }
+                            Some('\\') if matches!(self.peek(1), Some('"' | '$')) => {
+                                text.push(self.chars[self.pos + 1]);
+                                self.pos += 2;
+                            }
+                            Some('$') if self.peek(1) == Some('{') => {
+                                if !text.is_empty() { parts.push(WordPart::Quoted(std::mem::take(&mut text))); }
+                                let body = self.read_param()?;
+                                parts.push(WordPart::Param { body, quoted: true });
+                            }
+                            Some('$') if self.peek(1) == Some('(') => {
+                                if !text.is_empty() { parts.push(WordPart::Quoted(std::mem::take(&mut text))); }
+                                let src = self.read_subst()?;
+                                parts.push(WordPart::Subst { src, quoted: true });
+                            }
+                            Some('$') if self.peek(1).is_some_and(is_var_start) => {
+                                if !text.is_empty() { parts.push(WordPart::Quoted(std::mem::take(&mut text))); }
+                                let name = self.read_name();
+                                parts.push(WordPart::Var { name, quoted: true });
+                            }
+                            Some(c) => { text.push(c); self.pos += 1; }
+                        }
+                    }
+                    // `""` is an empty argument, but `"$@"` with no
+                    // parameters must not add one.
+                    if !text.is_empty() || parts.len() == first { parts.push(WordPart::Quoted(text)); }
+                }
+                '$' if self.peek(1) == Some('{') => {
+                    let body = self.read_param()?;
+                    parts.push(WordPart::Param { body, quoted: false });
+                }
+                '$' if self.peek(1) == Some('(') => {
+                    let src = self.read_subst()?;
+                    parts.push(WordPart::Subst { src, quoted: false });
+                }
+                '$' if self.peek(1).is_some_and(is_var_start) => {
+                    let name = self.read_name();
+                    parts.push(WordPart::Var { name, quoted: false });
+                }
+                _ => {
+                    match parts.last_mut() {
+                        Some(WordPart::Lit(s)) => s.push(c),
+                        _ => parts.push(WordPart::Lit(c.to_string())),
+                    }
+                    self.pos += 1;
+                }
+            }
+        }
+        if let Some((wc, w)) = word.take() { toks.push((wc, Tok::Word(w))); }
+        Ok(toks)
+    }
+
+    /// Read `$(...)`, leaving `pos` after the closing paren.
+    fn read_subst(&mut self) -> Result<String, ParseError> {
+        let end = subst_end(&self.chars, self.pos + 2)
+            .ok_or_else(|| self.err(self.pos + 1, "unterminated command substitution"))?;
+        let src = self.chars[self.pos + 2..end].iter().collect();
+        self.pos = end + 1;
+        Ok(src)
+    }
+
+    /// Read `${...}`, leaving `pos` after the closing brace.
+    fn read_param(&mut self) -> Result<String, ParseError> {
+        let end = brace_end(&self.chars, self.pos + 2)
+            .ok_or_else(|| self.err(self.pos + 1, "unterminated '${'"))?;
+        let body = self.chars[self.pos + 2..end].iter().collect();
+        self.pos = end + 1;
+        Ok(body)
+    }
+
+    /// Read `$name` or a one-character special like `$?`, leaving `pos`
+    /// after the name.
+    fn read_name(&mut self) -> String {
+        self.pos += 1;
+        // Positional parameters are one digit: `$10` is `$1` then `0`.
+        if let Some(c @ ('?' | '@' | '#' | '!' | '0'..='9')) = self.peek(0) { self.pos += 1; return c.into(); }
+        let start = self.pos;
+        while self.peek(0).is_some_and(is_name_char) { self.pos += 1; }
+        self.chars[start..self.pos].iter().collect()
+    }
+}
+
+// ── Parser ────────────────────────────────────────────────────
+
+/// Parse a plain list of words, such as the items of `for x in ...`.
+pub fn parse_words(src: &str, line: usize) -> Result<Vec<Word>, ParseError> {
+    let lexer = Lexer { chars: src.chars().collect(), pos: 0, line };
+    lexer.tokens()?.into_iter().map(|(col, tok)| match tok {
+        Tok::Word(w) => Ok(w),
+        _ => Err(ParseError { line, col, msg: "expected a list of words".into() }),
+    }).collect()
+}
+
+/// Parse one line of NesC. `line` is only used for error positions.
+pub fn parse(src: &str, line: usize) -> Result<List, ParseError> {
+    let lexer = Lexer { chars: src.chars().collect(), pos: 0, line };
+    let end_col = lexer.chars.len() + 1;
+    let toks = lexer.tokens()?;
+    let err = |col: usize, msg: &str| ParseError { line, col, msg: msg.into() };
+
+    let mut list = List::default();
+    let mut connector = Connector::Seq;
+    let mut pipeline: Pipeline = Vec::new();
+    let mut cmd = SimpleCommand::default();
+    let mut expect_cmd = false;
+    let mut it = toks.into_iter().peekable();
+    while let Some((col, tok)) = it.next() {
+        match tok {
+            Tok::Word(w) => { cmd.words.push(w); expect_cmd = false; }
+            Tok::Redir(RedirOp::ErrToOut) => cmd.redirects.push(Redirect { op: RedirOp::ErrToOut, target: Vec::new() }),
+            Tok::Redir(op) => match it.next() {
+                Some((_, Tok::Word(target))) => cmd.redirects.push(Redirect { op, target }),
+                Some((c, _)) => return Err(err(c, "expected a file name after redirection")),
+                None => return Err(err(end_col, "expected a file name after redirection")),
+            },
+            Tok::Pipe => {
+                if cmd.words.is_empty() { return Err(err(col, "expected a command before '|'")); }
+                pipeline.push(std::mem::take(&mut cmd));
+                if it.peek().is_none() { return Err(err(end_col, "expected a command after '|'")); }
+                expect_cmd = true;
+            }
+            Tok::AndAnd | Tok::OrOr | Tok::Semi | Tok::Amp => {
+                let (next, sym) = match tok {
+                    Tok::AndAnd => (Connector::And, "&&"),
+                    Tok::OrOr => (Connector::Or, "||"),
+                    Tok::Amp => (Connector::Seq, "&"),
+                    _ => (Connector::Seq, ";"),
+                };
+                if sym == "&" && cmd.words.is_empty() && pipeline.is_empty() {
+                    return Err(err(col, "expected a command before '&'"));
+                }
+                if cmd.words.is_empty()
+                    && (next != Connector::Seq || expect_cmd || !pipeline.is_empty() || !cmd.redirects.is_empty())
+                {
+                    return Err(err(col, &format!("expected a command before '{}'", sym)));
+                }
+                if !cmd.words.is_empty() { pipeline.push(std::mem::take(&mut cmd)); }
+                if !pipeline.is_empty() {
+                    let pipeline = std::mem::take(&mut pipeline);
+                    list.items.push(Item { connector, pipeline, background: sym == "&" });
+                }
+                if next != Connector::Seq && it.peek().is_none() {
+                    return Err(err(end_col, &format!("expected a command after '{}'", sym)));
+                }
+                connector = next;
+                expect_cmd = next != Connector::Seq;
+            }
+        }
+    }
+    if !cmd.words.is_empty() {
+        pipeline.push(cmd);
+    } else if !cmd.redirects.is_empty() {
+        return Err(err(end_col, "expected a command"));
+    }
+    if !pipeline.is_empty() { list.items.push(Item { connector, pipeline, background: false }); }
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of a word made only of literal and quoted parts.
+    fn text(word: &Word) -> String {
+        word.iter().map(|p| match p {
+            WordPart::Lit(s) | WordPart::Quoted(s) => s.as_str(),
+            other => panic!("unexpected part {:?}", other),
+        }).collect()
+    }
+
+    #[test]
+    fn quote_round_trips() {
+        let args = [
+            "plain", "", "two words", "*.rs", "a?b", "[ab]", "{a,b}", "a,b", "~", "~root/x", "x~",
+            "$HOME", "${x}", "$(id)", "it's", "'", "say \"hi\"", "a;b|c&d", "<in>", "2>err", "#not", "back\\slash",
+            "tab\there", "'*'\"$x\"",
+        ];
+        for arg in args {
+            let quoted = quote(arg);
+            let words = parse_words(&quoted, 1).unwrap_or_else(|e| panic!("{}: {}", quoted, e));
+            assert_eq!(words.len(), 1, "{}", quoted);
+            assert_eq!(text(&words[0]), arg, "{}", quoted);
+            // Nothing that expansion would touch is left unquoted.
+            for part in &words[0] {
+                if let WordPart::Lit(s) = part {
+                    assert!(!s.contains(|c| "*?[]{},".contains(c)) && !s.starts_with('~'), "{}", quoted);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quote_leaves_plain_words() {
+        assert_eq!(quote("src/main.rs"), "src/main.rs");
+        assert_eq!(quote("--flag=1"), "--flag=1");
+        assert_eq!(quote("*.rs"), "'*.rs'");
+        assert_eq!(quote("it's"), "'it'\"'\"'s'");
+    }
+
+    #[test]
+    fn lexer_parts() {
+        let words = parse_words(r#"a'b c'"d $x" $y ${z:-1} $(echo hi) "a\"b\$c""#, 1).unwrap();
+        assert_eq!(words.len(), 5);
+        assert!(matches!(&words[0][..], [WordPart::Lit(a), WordPart::Quoted(b), WordPart::Quoted(c), WordPart::Var { name, quoted: true }]
+            if a == "a" && b == "b c" && c == "d " && name == "x"));
+        assert!(matches!(&words[1][..], [WordPart::Var { name, quoted: false }] if name == "y"));
+        assert!(matches!(&words[2][..], [WordPart::Param { body, quoted: false }] if body == "z:-1"));
+        assert!(matches!(&words[3][..], [WordPart::Subst { src, quoted: false }] if src == "echo hi"));
+        assert_eq!(text(&words[4]), "a\"b$c");
+    }
+
+    #[test]
+    fn parse_connectors_and_redirects() {
+        let list = parse("a | b > out 2>&1 && c || d & e; f < in", 1).unwrap();
+        let shape: Vec<_> = list.items.iter().map(|i| (i.connector, i.pipeline.len(), i.background)).collect();
+        assert_eq!(shape, [
+            (Connector::Seq, 2, false), (Connector::And, 1, false), (Connector::Or, 1, true),
+            (Connector::Seq, 1, false), (Connector::Seq, 1, false),
+        ]);
+        let ops: Vec<_> = list.items[0].pipeline[1].redirects.iter().map(|r| r.op).collect();
+        assert_eq!(ops, [RedirOp::Write, RedirOp::ErrToOut]);
+        assert_eq!(text(&list.items[0].pipeline[1].redirects[0].target), "out");
+        assert_eq!(list.items[4].pipeline[0].redirects[0].op, RedirOp::Read);
+        assert!(parse("echo \"unterminated", 1).is_err());
+        assert!(parse("| b", 1).is_err());
+        assert!(parse("a |", 1).is_err());
+        assert!(parse("a &&", 1).is_err());
+        assert!(parse("echo >", 1).is_err());
+    }
+
+    #[test]
+    fn brace_end_skips_quotes() {
+        let end = |s: &str| brace_end(&s.chars().collect::<Vec<_>>(), 0);
+        assert_eq!(end("x}rest"), Some(1));
+        assert_eq!(end("x:-{a}}"), Some(6));
+        assert_eq!(end("x:-\"}\"}"), Some(6));
+        assert_eq!(end("x:-'}'}"), Some(6));
+        assert_eq!(end("x:-\"\\\"}\"}"), Some(8));
+        assert_eq!(end("x:-'}'"), None);
+        let words = parse_words(r#"${x:-"}"}"#, 1).unwrap();
+        assert!(matches!(&words[0][..], [WordPart::Param { body, .. }] if body == r#"x:-"}""#));
+    }
+}